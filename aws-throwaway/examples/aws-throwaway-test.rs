@@ -1,4 +1,4 @@
-use aws_throwaway::{Aws, InstanceType};
+use aws_throwaway::{Aws, Ec2InstanceDefinition, InstanceType};
 use std::path::Path;
 use tracing_subscriber::EnvFilter;
 
@@ -10,8 +10,11 @@ async fn main() {
         .with_writer(non_blocking)
         .init();
 
-    let aws = Aws::new().await;
-    let instance = aws.create_ec2_instance(InstanceType::T2Micro, 8).await;
+    let aws = Aws::new().await.unwrap();
+    let instance = aws
+        .create_ec2_instance(Ec2InstanceDefinition::new(InstanceType::T2Micro))
+        .await
+        .unwrap();
 
     instance
         .ssh()
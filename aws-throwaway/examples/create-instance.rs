@@ -1,4 +1,4 @@
-use aws_throwaway::{Aws, InstanceType};
+use aws_throwaway::{Aws, Ec2InstanceDefinition, InstanceType};
 use clap::Parser;
 use std::str::FromStr;
 use tracing_subscriber::EnvFilter;
@@ -18,9 +18,14 @@ async fn main() {
     } else if let Some(instance_type) = args.instance_type {
         println!("Creating instance of type {instance_type}");
 
-        let aws = Aws::new().await;
+        let aws = Aws::new().await.unwrap();
         let instance_type = InstanceType::from_str(&instance_type).unwrap();
-        let instance = aws.create_ec2_instance(instance_type, 20).await;
+        let instance = aws
+            .create_ec2_instance(
+                Ec2InstanceDefinition::new(instance_type).volume_size_gigabytes(20),
+            )
+            .await
+            .unwrap();
 
         let result = instance.ssh().shell("lsb_release -a").await;
         println!("Created instance running:\n{}", result.stdout);
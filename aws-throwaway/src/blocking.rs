@@ -0,0 +1,91 @@
+//! Synchronous wrappers around the async API for use outside of an async runtime.
+//! Only available with the `blocking` feature.
+//!
+//! These types must not be used from within an async context, as they block the current thread on an internal tokio runtime.
+
+use crate::ec2_instance::Ec2Instance;
+use crate::{Aws, AwsBuilder, BuildError, CommandOutput, Ec2InstanceDefinition, Ec2InstanceError};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// A blocking wrapper around [`Aws`]
+pub struct AwsBlocking {
+    aws: Aws,
+    // declared last so that the runtime is dropped after everything that may have spawned tasks on it
+    runtime: Arc<Runtime>,
+}
+
+impl AwsBlocking {
+    /// Equivalent to [`Aws::new`]
+    pub fn new() -> Result<Self, BuildError> {
+        AwsBlocking::from_builder(Aws::builder())
+    }
+
+    /// Equivalent to [`AwsBuilder::build`]
+    pub fn from_builder(builder: AwsBuilder) -> Result<Self, BuildError> {
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let aws = runtime.block_on(builder.build())?;
+        Ok(AwsBlocking { runtime, aws })
+    }
+
+    /// Equivalent to [`Aws::create_ec2_instance`]
+    pub fn create_ec2_instance(
+        &self,
+        definition: Ec2InstanceDefinition,
+    ) -> Result<Ec2InstanceBlocking, Ec2InstanceError> {
+        let instance = self
+            .runtime
+            .block_on(self.aws.create_ec2_instance(definition))?;
+        Ok(Ec2InstanceBlocking {
+            runtime: self.runtime.clone(),
+            instance,
+        })
+    }
+
+    /// Equivalent to [`Aws::cleanup_resources`]
+    pub fn cleanup_resources(&self) {
+        self.runtime.block_on(self.aws.cleanup_resources())
+    }
+
+    /// The wrapped [`Aws`], for its synchronous methods.
+    pub fn aws(&self) -> &Aws {
+        &self.aws
+    }
+}
+
+/// A blocking wrapper around [`Ec2Instance`], created by [`AwsBlocking::create_ec2_instance`]
+pub struct Ec2InstanceBlocking {
+    instance: Ec2Instance,
+    runtime: Arc<Runtime>,
+}
+
+impl Ec2InstanceBlocking {
+    /// Runs [`SshConnection::shell`](crate::ec2_instance::Ec2Instance::ssh) on the instance
+    pub fn shell(&self, command: &str) -> CommandOutput {
+        self.runtime.block_on(self.instance.ssh().shell(command))
+    }
+
+    /// Runs [`SshConnection::shell_checked`](crate::ec2_instance::Ec2Instance::ssh) on the instance
+    pub fn shell_checked(&self, command: &str) -> anyhow::Result<CommandOutput> {
+        self.runtime
+            .block_on(self.instance.ssh().shell_checked(command))
+    }
+
+    /// Runs [`SshConnection::push_file`](crate::ec2_instance::Ec2Instance::ssh) on the instance
+    pub fn push_file(&self, source: &Path, dest: &Path) {
+        self.runtime
+            .block_on(self.instance.ssh().push_file(source, dest))
+    }
+
+    /// Runs [`SshConnection::pull_file`](crate::ec2_instance::Ec2Instance::ssh) on the instance
+    pub fn pull_file(&self, source: &Path, dest: &Path) {
+        self.runtime
+            .block_on(self.instance.ssh().pull_file(source, dest))
+    }
+
+    /// The wrapped [`Ec2Instance`], for its synchronous methods such as [`Ec2Instance::public_ip`].
+    pub fn instance(&self) -> &Ec2Instance {
+        &self.instance
+    }
+}
@@ -0,0 +1,35 @@
+use crate::proxy;
+use anyhow::{anyhow, Result};
+use hyper::client::connect::Connect;
+use hyper::{Body, Client, Uri};
+
+const CHECK_IP_URL: &str = "https://checkip.amazonaws.com";
+
+/// Looks up the public IP that requests from this machine originate from, sending the request through `https_proxy` if set.
+pub async fn public_ip(https_proxy: Option<&str>) -> Result<String> {
+    let ip = match https_proxy {
+        Some(proxy_url) => fetch(proxy::https_connector(proxy_url)?).await?,
+        None => {
+            let connector = hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_only()
+                .enable_http1()
+                .build();
+            fetch(connector).await?
+        }
+    };
+    tracing::info!("detected public IP of this machine as {ip}");
+    Ok(ip)
+}
+
+async fn fetch<C: Connect + Clone + Send + Sync + 'static>(connector: C) -> Result<String> {
+    let client: Client<C, Body> = Client::builder().build(connector);
+    let response = client
+        .get(Uri::from_static(CHECK_IP_URL))
+        .await
+        .map_err(|e| anyhow!(e).context(format!("Failed to request {CHECK_IP_URL}")))?;
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| anyhow!(e).context(format!("Failed to read response from {CHECK_IP_URL}")))?;
+    Ok(String::from_utf8_lossy(&body).trim().to_owned())
+}
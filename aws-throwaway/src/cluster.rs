@@ -0,0 +1,360 @@
+use crate::ec2_instance::Ec2Instance;
+use crate::ec2_instance_definition::is_valid_hostname;
+use crate::ssh::{local_permissions, CommandOutput};
+use crate::Aws;
+use anyhow::{anyhow, bail, Result};
+use futures::future::{join_all, try_join_all};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+impl Aws {
+    /// Pushes the local file `source` to `dest` on every instance concurrently, e.g. to distribute a build artifact to every node of a cluster.
+    /// The file is read once and, as with [`SshConnection::push_file`](crate::ssh::SshConnection::push_file), its permission bits are preserved on unix.
+    ///
+    /// Returns the result of the push to each instance, in the same order as `instances`,
+    /// a failure to push to one instance does not prevent pushing to the others.
+    pub async fn broadcast_file(
+        &self,
+        instances: &[&Ec2Instance],
+        source: &Path,
+        dest: &Path,
+    ) -> Result<Vec<Result<()>>> {
+        let bytes = tokio::fs::read(source)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to read from {source:?}")))?;
+        let metadata = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to read from {source:?}")))?;
+        let mode = local_permissions(&metadata).unwrap_or(0o777);
+        Ok(join_all(instances.iter().map(|instance| {
+            let bytes = &bytes;
+            async move {
+                instance
+                    .ssh()
+                    .try_push_bytes_with_mode(bytes, dest, mode)
+                    .await
+                    .map_err(|e| {
+                        e.context(format!(
+                            "Failed to push {source:?} to instance {}",
+                            instance.instance_id()
+                        ))
+                    })
+            }
+        }))
+        .await)
+    }
+
+    /// Measures the clock of each instance against the local clock and reports the skew between them.
+    ///
+    /// If `resync_threshold` is set and the skew between any two instances exceeds it,
+    /// every instance is told to immediately step its clock to NTP time and the clocks are measured again.
+    ///
+    /// Returns an error naming the instance if the clock of any instance could not be read.
+    pub async fn verify_clock_sync(
+        &self,
+        instances: &[&Ec2Instance],
+        resync_threshold: Option<Duration>,
+    ) -> Result<ClockReport> {
+        let mut report = measure_clocks(instances).await?;
+        if let Some(threshold) = resync_threshold {
+            if report.max_skew_secs > threshold.as_secs_f64() {
+                tracing::info!(
+                    "clock skew of {}s exceeds threshold of {threshold:?}, forcing resync",
+                    report.max_skew_secs
+                );
+                join_all(instances.iter().map(|instance| {
+                    instance.ssh().shell(
+                        "sudo chronyc makestep 2> /dev/null || sudo systemctl restart systemd-timesyncd",
+                    )
+                }))
+                .await;
+                report = measure_clocks(instances).await?;
+                report.resynced = true;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Waits until every instance can open a TCP connection to `port` on the private IP of every other instance.
+    ///
+    /// Returns an error listing the connections that still fail if the mesh is not fully connected within `timeout`.
+    pub async fn wait_for_mesh(
+        &self,
+        instances: &[&Ec2Instance],
+        port: u16,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let missing: Vec<(IpAddr, IpAddr)> = join_all(
+                instances
+                    .iter()
+                    .map(|instance| unreachable_peers(instance, instances, port)),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+            if missing.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                let edges: Vec<String> = missing
+                    .iter()
+                    .map(|(from, to)| format!("{from} -> {to}:{port}"))
+                    .collect();
+                bail!(
+                    "Instances could not reach each other within {timeout:?}, failed connections:\n{}",
+                    edges.join("\n")
+                );
+            }
+            tracing::info!(
+                "{} connections in the mesh are not yet established, retrying",
+                missing.len()
+            );
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Adds an `/etc/hosts` entry on every node mapping each node's hostname to its private IP, so that the nodes can resolve each other by name.
+    ///
+    /// The entries are written to a block managed by aws-throwaway which is replaced on each call,
+    /// so it can be called again with the full list of nodes after adding nodes.
+    /// Any other entries in `/etc/hosts` are left untouched.
+    pub async fn write_hosts_entries(&self, nodes: &[(&Ec2Instance, &str)]) -> Result<()> {
+        if let Some((_, hostname)) = nodes.iter().find(|(_, x)| !is_valid_hostname(x)) {
+            bail!("The hostname {hostname:?} is not a valid RFC 1123 hostname");
+        }
+
+        // The hostnames have been validated so it is safe to insert them into the script unescaped.
+        let entries: Vec<String> = nodes
+            .iter()
+            .map(|(instance, hostname)| format!("'{} {hostname}'", instance.private_ip()))
+            .collect();
+        let command = format!(
+            "sudo sed -i '/^{HOSTS_BEGIN}$/,/^{HOSTS_END}$/d' /etc/hosts\nprintf '%s\\n' '{HOSTS_BEGIN}' {} '{HOSTS_END}' | sudo tee -a /etc/hosts > /dev/null",
+            entries.join(" ")
+        );
+
+        let results = join_all(nodes.iter().map(|(instance, _)| {
+            let command = &command;
+            async move {
+                instance.ssh().shell_checked(command).await.map_err(|e| {
+                    e.context(format!(
+                        "Failed to write /etc/hosts on instance {}",
+                        instance.instance_id()
+                    ))
+                })
+            }
+        }))
+        .await;
+        for result in results {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Runs `command` on every instance so that it begins at the same instant on all of them, then waits for every command to complete.
+    ///
+    /// The clock offset of each instance is measured first, then each instance is told to sleep until a common start time
+    /// a short while in the future, translated into its own clock, so no coordinator is involved once the commands are scheduled.
+    /// The returned report includes how far apart the commands actually started.
+    pub async fn synchronized_start(
+        &self,
+        instances: &[&Ec2Instance],
+        command: &str,
+    ) -> Result<SynchronizedStart> {
+        let clocks = measure_clocks(instances).await?;
+        let max_round_trip = clocks
+            .nodes
+            .iter()
+            .map(|x| x.round_trip_secs)
+            .fold(0.0, f64::max);
+        // leave enough time for the command to reach every instance before the start time
+        let start = now_secs() + 1.0 + max_round_trip * 4.0;
+
+        let outputs = join_all(instances.iter().zip(&clocks.nodes).map(
+            |(instance, clock)| async move {
+                let remote_start = start + clock.offset_secs;
+                instance.ssh().shell(&format!(
+                    "now=$(date +%s.%N); sleep $(awk -v start={remote_start:.6} -v now=$now 'BEGIN {{ d = start - now; print (d > 0 ? d : 0) }}'); date +%s.%N; {command}"
+                ))
+                .await
+            },
+        ))
+        .await;
+
+        let mut nodes = vec![];
+        for (mut output, clock) in outputs.into_iter().zip(clocks.nodes) {
+            let (started, stdout) = output
+                .stdout
+                .split_once('\n')
+                .unwrap_or((&output.stdout, ""));
+            let started: f64 = started.trim().parse().map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to parse start time of command on {}: {e}\n{output}",
+                    clock.private_ip
+                )
+            })?;
+            let start_offset_secs = started - clock.offset_secs - start;
+            output.stdout = stdout.to_owned();
+            nodes.push(NodeStart {
+                private_ip: clock.private_ip,
+                start_offset_secs,
+                output,
+            });
+        }
+        let max = nodes
+            .iter()
+            .map(|x| x.start_offset_secs)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let min = nodes
+            .iter()
+            .map(|x| x.start_offset_secs)
+            .fold(f64::INFINITY, f64::min);
+        Ok(SynchronizedStart {
+            spread_secs: if nodes.is_empty() { 0.0 } else { max - min },
+            nodes,
+        })
+    }
+}
+
+const HOSTS_BEGIN: &str = "# BEGIN aws-throwaway cluster hosts";
+const HOSTS_END: &str = "# END aws-throwaway cluster hosts";
+
+/// Returns the (from, to) private IPs of every peer that `instance` cannot connect to on `port`.
+async fn unreachable_peers(
+    instance: &Ec2Instance,
+    peers: &[&Ec2Instance],
+    port: u16,
+) -> Vec<(IpAddr, IpAddr)> {
+    let peers: Vec<IpAddr> = peers
+        .iter()
+        .map(|x| x.private_ip())
+        .filter(|x| *x != instance.private_ip())
+        .collect();
+    if peers.is_empty() {
+        return vec![];
+    }
+
+    let checks: Vec<String> = peers
+        .iter()
+        .map(|ip| format!("timeout 1 bash -c '</dev/tcp/{ip}/{port}' 2> /dev/null && echo {ip}"))
+        .collect();
+    let reachable = instance
+        .ssh()
+        .shell(&format!("{}; true", checks.join("; ")))
+        .await
+        .stdout;
+    let reachable: Vec<&str> = reachable.lines().collect();
+    peers
+        .into_iter()
+        .filter(|ip| !reachable.contains(&ip.to_string().as_str()))
+        .map(|ip| (instance.private_ip(), ip))
+        .collect()
+}
+
+async fn measure_clocks(instances: &[&Ec2Instance]) -> Result<ClockReport> {
+    let nodes: Vec<NodeClock> = try_join_all(instances.iter().map(|x| measure_clock(x))).await?;
+    let max = nodes
+        .iter()
+        .map(|x| x.offset_secs)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min = nodes
+        .iter()
+        .map(|x| x.offset_secs)
+        .fold(f64::INFINITY, f64::min);
+    Ok(ClockReport {
+        max_skew_secs: if nodes.is_empty() { 0.0 } else { max - min },
+        nodes,
+        resynced: false,
+    })
+}
+
+async fn measure_clock(instance: &Ec2Instance) -> Result<NodeClock> {
+    let synchronized = instance
+        .ssh()
+        .shell("timedatectl show -p NTPSynchronized --value")
+        .await
+        .stdout
+        .trim()
+        == "yes";
+
+    let before = now_secs();
+    let remote = instance.ssh().shell_checked("date +%s.%N").await;
+    let after = now_secs();
+    let remote = remote
+        .map_err(|e| {
+            e.context(format!(
+                "Failed to read the clock of instance {}",
+                instance.instance_id()
+            ))
+        })?
+        .stdout;
+    let remote: f64 = remote.trim().parse().map_err(|e| {
+        anyhow!(
+            "Failed to parse the time {remote:?} reported by instance {}: {e}",
+            instance.instance_id()
+        )
+    })?;
+
+    Ok(NodeClock {
+        private_ip: instance.private_ip(),
+        // assume the remote clock was read halfway through the round trip
+        offset_secs: remote - (before + after) / 2.0,
+        round_trip_secs: after - before,
+        synchronized,
+    })
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// The result of [`Aws::verify_clock_sync`]
+#[derive(Debug, Clone)]
+pub struct ClockReport {
+    /// The clock of each instance, in the same order as the instances were passed in.
+    pub nodes: Vec<NodeClock>,
+    /// The largest difference in seconds between the clocks of any two instances.
+    pub max_skew_secs: f64,
+    /// True if the skew exceeded the threshold and a resync was forced before taking these measurements.
+    pub resynced: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeClock {
+    pub private_ip: IpAddr,
+    /// How far ahead of the local clock the instance's clock is, in seconds.
+    /// Only accurate to within half of `round_trip_secs`.
+    pub offset_secs: f64,
+    /// How long it took to read the instance's clock over ssh.
+    pub round_trip_secs: f64,
+    /// Whether the instance reports its clock as synchronized to NTP.
+    pub synchronized: bool,
+}
+
+/// The result of [`Aws::synchronized_start`]
+#[derive(Debug)]
+pub struct SynchronizedStart {
+    /// Each instance's command, in the same order as the instances were passed in.
+    pub nodes: Vec<NodeStart>,
+    /// The largest difference in seconds between the actual start times of the command on any two instances.
+    /// Only accurate to within the round trip time of measuring the instances' clocks.
+    pub spread_secs: f64,
+}
+
+#[derive(Debug)]
+pub struct NodeStart {
+    pub private_ip: IpAddr,
+    /// How long after the scheduled start time the command actually started, in seconds.
+    pub start_offset_secs: f64,
+    /// The output of the command.
+    pub output: CommandOutput,
+}
@@ -0,0 +1,96 @@
+use aws_sdk_ec2::types::InstanceType;
+
+/// The CPU architecture of an instance type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuArch {
+    X86_64,
+    Aarch64,
+}
+
+impl CpuArch {
+    pub(crate) fn get_ubuntu_arch_identifier(&self) -> &'static str {
+        match self {
+            CpuArch::X86_64 => "amd64",
+            CpuArch::Aarch64 => "arm64",
+        }
+    }
+
+    pub(crate) fn get_amazon_linux_arch_identifier(&self) -> &'static str {
+        match self {
+            CpuArch::X86_64 => "x86_64",
+            CpuArch::Aarch64 => "arm64",
+        }
+    }
+}
+
+/// Returns None if the instance type does not follow the usual naming scheme
+pub(crate) fn get_arch_of_instance_type(instance_type: &InstanceType) -> Option<CpuArch> {
+    // Instance names looke like something like:
+    // type + revision_number + subtypes + '.' + size
+    // So say for example `Im4gn.large` would be split into:
+    // type = "Im"
+    // revision_number = 4
+    // subtypes = "gn"
+    // size = "large"
+    //
+    // The 'g' character existing in subtypes indicates that the instance type is a gravitron aka arm instance.
+    // We can check for the existence of 'g' to determine if we are aarch64 or x86_64
+    // This is a bit hacky because this format is not explicitly documented anywhere but the instance type naming does consistently follow this pattern.
+    //
+    // A few graviton/arm families predate or otherwise break this pattern and so are special cased:
+    // * a1 is the first generation graviton family and has no 'g' subtype.
+    // * mac2 (including mac2-m2 and mac2-m2pro) are apple silicon, while mac1 is intel.
+    let name = instance_type.as_str();
+    if name.starts_with("a1.") || name.starts_with("mac2") {
+        return Some(CpuArch::Aarch64);
+    }
+    if name.starts_with("mac1.") {
+        return Some(CpuArch::X86_64);
+    }
+
+    let mut reached_revision_number = false;
+    for c in name.chars() {
+        if !reached_revision_number {
+            if c.is_ascii_digit() {
+                reached_revision_number = true;
+            }
+        } else if c == '.' {
+            return Some(CpuArch::X86_64);
+        } else if c == 'g' {
+            return Some(CpuArch::Aarch64);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ubuntu_arch(instance_type: &str) -> Option<&'static str> {
+        get_arch_of_instance_type(&InstanceType::from(instance_type))
+            .map(|arch| arch.get_ubuntu_arch_identifier())
+    }
+
+    #[test]
+    fn graviton_instance_types() {
+        assert_eq!(ubuntu_arch("c7g.large"), Some("arm64"));
+        assert_eq!(ubuntu_arch("r7g.xlarge"), Some("arm64"));
+        assert_eq!(ubuntu_arch("m7g.medium"), Some("arm64"));
+        assert_eq!(ubuntu_arch("i4g.large"), Some("arm64"));
+        assert_eq!(ubuntu_arch("a1.large"), Some("arm64"));
+    }
+
+    #[test]
+    fn mac_instance_types() {
+        assert_eq!(ubuntu_arch("mac1.metal"), Some("amd64"));
+        assert_eq!(ubuntu_arch("mac2.metal"), Some("arm64"));
+        assert_eq!(ubuntu_arch("mac2-m2pro.metal"), Some("arm64"));
+    }
+
+    #[test]
+    fn x86_instance_types() {
+        assert_eq!(ubuntu_arch("c7i.large"), Some("amd64"));
+        assert_eq!(ubuntu_arch("t3.micro"), Some("amd64"));
+    }
+}
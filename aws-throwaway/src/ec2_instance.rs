@@ -1,18 +1,156 @@
+use anyhow::{anyhow, bail};
+use aws_sdk_ec2::types::{
+    Instance, InstanceStateName, SummaryStatus, Tag, VolumeModificationState,
+};
+use base64::Engine;
+use std::path::{Path, PathBuf};
 use std::{net::IpAddr, time::Duration};
 
 use tokio::{net::TcpStream, time::Instant};
 
-use crate::ssh::SshConnection;
+use crate::ssh::{
+    shell_escape, PortForward, SshConnectError, SshConnection, SshCredentials, SshStream,
+};
+use crate::{BlockDevice, Ec2InstanceError, TAG_PREFIX};
 
 pub struct Ec2Instance {
-    public_ip: IpAddr,
+    client: aws_sdk_ec2::Client,
+    audit_log: bool,
+    instance_id: String,
+    public_ip: Option<IpAddr>,
     private_ip: IpAddr,
-    client_private_key: String,
+    private_dns_name: String,
+    connect_via_public_ip: bool,
+    credentials: SshCredentials,
+    placement: InstancePlacement,
+    tags: Vec<(String, String)>,
+    block_devices: Vec<BlockDevice>,
+    network_interfaces: Vec<NetworkInterface>,
+    elastic_ip_allocation_id: Option<String>,
+    elastic_ip: Option<IpAddr>,
+    creation_timings: Option<CreationTimings>,
+    ssh_connect_timeout: Duration,
+    state_change_timeout: Duration,
     ssh: SshConnection,
 }
 
+/// Everything known about an instance once it has been launched and assigned its IPs
+pub(crate) struct LaunchedInstance {
+    pub client: aws_sdk_ec2::Client,
+    pub audit_log: bool,
+    pub instance_id: String,
+    pub public_ip: Option<IpAddr>,
+    pub connect_via_public_ip: bool,
+    pub private_ip: IpAddr,
+    pub private_dns_name: String,
+    pub placement: InstancePlacement,
+    pub tags: Vec<(String, String)>,
+    pub block_devices: Vec<BlockDevice>,
+    pub network_interfaces: Vec<NetworkInterface>,
+    pub elastic_ip_allocation_id: Option<String>,
+    pub elastic_ip: Option<IpAddr>,
+    /// `ssh_connect` is filled in once the ssh connection is established
+    pub creation_timings: Option<CreationTimings>,
+    pub ssh_connect_timeout: Duration,
+    /// How long to wait for the instance to reach a new state, e.g. when stopping it
+    pub state_change_timeout: Duration,
+}
+
+/// How long each phase of creating an instance took, see [`Ec2Instance::creation_timings`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CreationTimings {
+    /// The `RunInstances` request, shared by all identical instances created in the same call
+    pub launch: Duration,
+    /// Waiting for AWS to assign the instance its IP addresses, including associating its elastic IP if any
+    pub ip_assignment: Duration,
+    /// Waiting for the instance to boot and accept an ssh connection
+    pub ssh_connect: Duration,
+}
+
+/// A network interface attached to an instance
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkInterface {
+    /// The position of the interface on the instance, the primary interface is 0
+    pub device_index: u32,
+    /// The id of the interface, e.g. `eni-0123456789abcdef0`, for use with other AWS APIs
+    pub network_interface_id: String,
+    pub private_ipv4: IpAddr,
+    /// The public or elastic IP associated with the interface, if any
+    pub public_ipv4: Option<IpAddr>,
+    /// The IPv6 addresses assigned to the interface, see [`Ec2InstanceDefinition::ipv6_address_count`](crate::Ec2InstanceDefinition::ipv6_address_count)
+    pub ipv6_addresses: Vec<IpAddr>,
+}
+
+impl NetworkInterface {
+    /// Returns the interfaces of `instance` ordered by device index.
+    pub(crate) fn all_from_aws(instance: &aws_sdk_ec2::types::Instance) -> Vec<Self> {
+        let mut interfaces: Vec<Self> = instance
+            .network_interfaces()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|interface| {
+                Some(NetworkInterface {
+                    device_index: interface.attachment()?.device_index()? as u32,
+                    network_interface_id: interface.network_interface_id()?.to_owned(),
+                    private_ipv4: interface.private_ip_address()?.parse().ok()?,
+                    public_ipv4: interface
+                        .association()
+                        .and_then(|association| association.public_ip())
+                        .and_then(|ip| ip.parse().ok()),
+                    ipv6_addresses: interface
+                        .ipv6_addresses()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|address| address.ipv6_address()?.parse().ok())
+                        .collect(),
+                })
+            })
+            .collect();
+        interfaces.sort_by_key(|interface| interface.device_index);
+        interfaces
+    }
+}
+
+/// Where AWS actually placed an instance
+#[derive(Default)]
+pub(crate) struct InstancePlacement {
+    availability_zone: String,
+    placement_group: Option<String>,
+    partition_number: Option<u32>,
+}
+
+impl InstancePlacement {
+    pub(crate) fn from_aws(placement: &aws_sdk_ec2::types::Placement) -> Self {
+        InstancePlacement {
+            availability_zone: placement.availability_zone().unwrap_or_default().to_owned(),
+            placement_group: placement.group_name().map(|x| x.to_owned()),
+            partition_number: placement.partition_number().map(|x| x as u32),
+        }
+    }
+}
+
 impl Ec2Instance {
+    /// The EC2 instance id, e.g. `i-0123456789abcdef0`, for use with other AWS APIs
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// The public IP of the instance.
+    ///
+    /// Panics if the instance has no public IP, which can only happen when it is not connected to via its public IP,
+    /// see [`Ec2Instance::connect_ip`]. Use [`Ec2Instance::try_public_ip`] if that is the case.
     pub fn public_ip(&self) -> IpAddr {
+        self.public_ip.unwrap_or_else(|| {
+            panic!(
+                "The instance {} has no public IP, use Ec2Instance::try_public_ip instead",
+                self.instance_id
+            )
+        })
+    }
+
+    /// The public IP of the instance, or `None` if it was not assigned one.
+    /// Only guaranteed to be present when the instance is connected to via its public IP, see [`Ec2Instance::connect_ip`].
+    pub fn try_public_ip(&self) -> Option<IpAddr> {
         self.public_ip
     }
 
@@ -20,80 +158,1015 @@ impl Ec2Instance {
         self.private_ip
     }
 
+    /// The IP that aws-throwaway connects to the instance over ssh with,
+    /// either the public or private IP depending on [`AwsBuilder::use_public_addresses`](crate::AwsBuilder::use_public_addresses)
+    /// and [`Ec2InstanceDefinition::connect_via_public_ip`](crate::Ec2InstanceDefinition::connect_via_public_ip).
+    pub fn connect_ip(&self) -> IpAddr {
+        match self.public_ip {
+            Some(public_ip) if self.connect_via_public_ip => public_ip,
+            _ => self.private_ip,
+        }
+    }
+
+    /// The private DNS hostname of the instance, resolvable from within the VPC.
+    /// Its format can be configured via [`Ec2InstanceDefinition::private_dns_hostname_type`](crate::Ec2InstanceDefinition::private_dns_hostname_type).
+    pub fn private_dns_name(&self) -> &str {
+        &self.private_dns_name
+    }
+
+    /// The availability zone the instance was launched in, e.g. `us-east-1c`
+    pub fn availability_zone(&self) -> &str {
+        &self.placement.availability_zone
+    }
+
+    /// The placement group the instance was launched into, if any.
+    pub fn placement_group(&self) -> Option<&str> {
+        self.placement.placement_group.as_deref()
+    }
+
+    /// The partition of the placement group the instance was launched into, only set for partition placement groups.
+    pub fn partition_number(&self) -> Option<u32> {
+        self.placement.partition_number
+    }
+
+    /// The OpenSSH private key used to authenticate with the instance, for use with external ssh tooling.
     pub fn client_private_key(&self) -> &str {
-        &self.client_private_key
+        &self.credentials.client_private_key
+    }
+
+    /// A line suitable for a `known_hosts` file, allowing external ssh tooling to verify the instance's host key.
+    pub fn known_hosts_entry(&self) -> String {
+        let host_public_key =
+            ssh_key::PublicKey::from_bytes(&self.credentials.host_public_key_bytes)
+                .and_then(|key| key.to_openssh())
+                .unwrap();
+        format!("{} {host_public_key}", self.connect_ip())
+    }
+
+    /// The key material needed to reconnect to this instance via [`Aws::attach_to_instance`](crate::Aws::attach_to_instance),
+    /// e.g. from another process after this one has crashed.
+    pub fn keys(&self) -> InstanceKeys {
+        InstanceKeys {
+            user: self.credentials.user.clone(),
+            client_private_key: self.credentials.client_private_key.clone(),
+            host_public_key: ssh_key::PublicKey::from_bytes(
+                &self.credentials.host_public_key_bytes,
+            )
+            .and_then(|key| key.to_openssh())
+            .unwrap(),
+        }
+    }
+
+    /// The network interfaces attached to the instance, starting with the primary interface.
+    /// Public IPs reflect the state of the instance when it was created or attached to.
+    pub fn network_interfaces(&self) -> &[NetworkInterface] {
+        &self.network_interfaces
+    }
+
+    /// How long each phase of creating the instance took, useful for finding where boot time goes.
+    /// The timings are also logged with structured fields when the instance is created.
+    /// Returns `None` for instances obtained via [`Aws::attach_to_instance`](crate::Aws::attach_to_instance).
+    pub fn creation_timings(&self) -> Option<&CreationTimings> {
+        self.creation_timings.as_ref()
+    }
+
+    /// The allocation id of the elastic IP associated with the instance, if any.
+    /// Needed to release the elastic IP if the instance is terminated outside of aws-throwaway.
+    pub fn elastic_ip_allocation_id(&self) -> Option<&str> {
+        self.elastic_ip_allocation_id.as_deref()
     }
 
+    /// The public IP of the elastic IP associated with the instance, if any, e.g. for updating DNS records.
+    /// When set this is also the value of [`Ec2Instance::public_ip`].
+    pub fn elastic_ip(&self) -> Option<IpAddr> {
+        self.elastic_ip
+    }
+
+    /// The tags applied to the instance and its volumes, including those added by aws-throwaway itself.
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    /// The EBS volumes attached to the instance, starting with the root volume.
+    pub fn block_devices(&self) -> &[BlockDevice] {
+        &self.block_devices
+    }
+
+    /// The persistent ssh connection to the instance, established when the instance was created.
+    /// Every command run through it reuses the same connection, so there is no need to manage a separate session for running many commands.
+    /// The connection is closed when the [`Ec2Instance`] is dropped.
     pub fn ssh(&self) -> &SshConnection {
         &self.ssh
     }
 
+    /// Shell commands for manually connecting to the instance over ssh.
+    /// When a jump host is configured the instructions route through it with `-J`,
+    /// which requires the jump host's key to already be usable by ssh, e.g. via ssh-agent.
     pub fn ssh_instructions(&self) -> String {
+        let jump = match &self.credentials.jump_host {
+            Some(jump_host) => format!("-J {} ", jump_host.proxy_jump()),
+            None => String::new(),
+        };
         format!(
             r#"
 ```
 chmod 700 key 2> /dev/null || true
 echo '{}' > key
 chmod 400 key
-TERM=xterm ssh -i key ubuntu@{}
+TERM=xterm ssh -i key {jump}{}@{}
 ```"#,
             self.client_private_key(),
-            self.public_ip()
+            self.credentials.user,
+            self.connect_ip()
         )
     }
 
-    pub(crate) async fn new(
-        public_ip: IpAddr,
-        private_ip: IpAddr,
-        host_public_key_bytes: Vec<u8>,
-        client_private_key: &str,
-    ) -> Self {
+    /// Forwards `local_port` on the local machine to `remote_port` on the instance through the ssh connection,
+    /// allowing services on the instance to be reached without opening their ports to the internet.
+    /// The tunnel is closed when the returned [`PortForward`] is dropped.
+    pub async fn forward_local_port(
+        &self,
+        local_port: u16,
+        remote_port: u16,
+    ) -> anyhow::Result<PortForward> {
+        self.ssh.forward_local_port(local_port, remote_port).await
+    }
+
+    /// Waits until a service on the instance is accepting TCP connections on `port`, or returns an error if `timeout` is exceeded.
+    ///
+    /// The connection attempts are tunneled through ssh, so this works for ports that are not open to the internet.
+    /// Services listening on localhost, the private IP or all interfaces are all detected.
+    pub async fn wait_for_port(&self, port: u16, timeout: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let private_ip = self.private_ip.to_string();
         loop {
-            let start = Instant::now();
-            // We retry many times before we are able to succesfully make an ssh connection.
-            // Each error is expected and so is logged as a `info!` that describes the underlying startup process that is supposed to cause the error.
-            // A numbered comment is left before each `info!` to demonstrate the order each error occurs in.
-            match tokio::time::timeout(Duration::from_secs(10), TcpStream::connect((public_ip, 22)))
-                .await
+            if self.ssh.port_is_open("127.0.0.1", port).await
+                || self.ssh.port_is_open(&private_ip, port).await
             {
-                Err(_) => {
-                    // 1.
-                    tracing::info!("Timed out connecting to {public_ip} over ssh, the host is probably not accessible yet, retrying");
-                    continue;
-                }
-                Ok(Err(e)) => {
-                    // 2.
-                    tracing::info!("failed to connect to {public_ip}:22, the host probably hasnt started their ssh service yet, retrying, error was {e}");
-                    tokio::time::sleep_until(start + Duration::from_secs(1)).await;
-                    continue;
-                }
-                Ok(Ok(stream)) => {
-                    match SshConnection::new(
-                        stream,
-                        public_ip,
-                        host_public_key_bytes.clone(),
-                        client_private_key,
-                    )
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "Port {port} on instance {} did not open within {timeout:?}",
+                    self.instance_id
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Mounts a tmpfs ramdisk of `size_mb` megabytes at `mount_point`, creating the directory if needed.
+    /// Returns the absolute path the tmpfs was mounted at.
+    ///
+    /// Fails without mounting anything if the instance does not have enough memory to back the tmpfs.
+    pub async fn mount_tmpfs(&self, mount_point: &str, size_mb: u32) -> anyhow::Result<String> {
+        let meminfo = self
+            .ssh
+            .shell_checked("grep MemTotal /proc/meminfo")
+            .await?
+            .stdout;
+        let total_kb: u64 = meminfo
+            .split_whitespace()
+            .nth(1)
+            .and_then(|kb| kb.parse().ok())
+            .ok_or_else(|| anyhow!("Failed to parse MemTotal from {meminfo:?}"))?;
+        let total_mb = total_kb / 1024;
+        if size_mb as u64 > total_mb {
+            bail!(
+                "Cannot mount a {size_mb}MB tmpfs on an instance with only {total_mb}MB of memory"
+            );
+        }
+
+        let escaped = shell_escape(mount_point);
+        self.ssh
+            .shell_checked(&format!(
+                "sudo mkdir -p {escaped} && sudo mount -t tmpfs -o size={size_mb}M tmpfs {escaped}"
+            ))
+            .await?;
+        let mounted = self
+            .ssh
+            .shell_checked(&format!("findmnt -n -o TARGET {escaped}"))
+            .await?
+            .stdout;
+        Ok(mounted.trim().to_owned())
+    }
+
+    /// Collects diagnostics from the instance into a gzipped tarball and pulls it into `local_dir`, returning the path of the local tarball.
+    ///
+    /// The tarball contains the systemd journal in journalctl export format, the cloud-init logs, dmesg and the disk and mount state,
+    /// which is useful to attach to a bug report after a test fails.
+    pub async fn export_diagnostics(&self, local_dir: &Path) -> anyhow::Result<PathBuf> {
+        let name = format!("aws-throwaway-diagnostics-{}", self.instance_id);
+        let remote_tarball = format!("/tmp/{name}.tar.gz");
+        self.ssh
+            .shell_checked(&format!(
+                r#"dir=/tmp/{name}
+rm -rf $dir && mkdir -p $dir
+sudo journalctl --no-pager -o export > $dir/journal.export
+sudo cp /var/log/cloud-init.log /var/log/cloud-init-output.log $dir/ 2> /dev/null
+sudo dmesg > $dir/dmesg.log
+df -h > $dir/df.log
+lsblk > $dir/lsblk.log
+findmnt > $dir/findmnt.log
+sudo tar -czf {remote_tarball} -C /tmp {name} && sudo chown "$(id -u):$(id -g)" {remote_tarball}"#
+            ))
+            .await?;
+
+        tokio::fs::create_dir_all(local_dir)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to create {local_dir:?}")))?;
+        let local_tarball = local_dir.join(format!("{name}.tar.gz"));
+        // pull_file chmods the source after reading it, so the tarball must be owned by the ssh user rather than root.
+        self.ssh
+            .pull_file(Path::new(&remote_tarball), &local_tarball)
+            .await;
+        self.ssh
+            .shell(&format!("sudo rm -rf {remote_tarball} /tmp/{name}"))
+            .await;
+        Ok(local_tarball)
+    }
+
+    /// Returns a future that polls the instance metadata service over ssh every `poll_interval`.
+    /// The future resolves once a spot interruption notice or scheduled maintenance event is reported,
+    /// giving the caller a chance to checkpoint before the instance is stopped or terminated.
+    pub async fn termination_watcher(&self, poll_interval: Duration) -> TerminationNotice {
+        loop {
+            let spot = self.query_metadata("spot/instance-action").await;
+            if !spot.is_empty() {
+                return TerminationNotice::SpotInterruption(spot);
+            }
+
+            let events = self.query_metadata("events/maintenance/scheduled").await;
+            if !events.is_empty() && events != "[]" {
+                return TerminationNotice::ScheduledMaintenance(events);
+            }
+
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Fetches a path from the instance metadata service, returning an empty string if it does not exist.
+    async fn query_metadata(&self, path: &str) -> String {
+        let command = format!(
+            r#"TOKEN=$(curl -s -X PUT "http://169.254.169.254/latest/api/token" -H "X-aws-ec2-metadata-token-ttl-seconds: 60")
+curl -s -f -H "X-aws-ec2-metadata-token: $TOKEN" "http://169.254.169.254/latest/meta-data/{path}" || true"#
+        );
+        self.ssh.shell(&command).await.stdout.trim().to_owned()
+    }
+
+    /// Stops the instance and waits for it to reach the stopped state.
+    /// The instance can later be restarted with [`Ec2Instance::start`].
+    /// While stopped the ssh connection is unusable.
+    pub async fn stop(&self) -> anyhow::Result<()> {
+        tracing::info!("Stopping instance {}", self.instance_id);
+        audit!(
+            self.audit_log,
+            "StopInstances",
+            instance_id = self.instance_id
+        );
+        self.client
+            .stop_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        self.wait_for_state(InstanceStateName::Stopped).await?;
+        Ok(())
+    }
+
+    /// Hibernates the instance and waits for it to reach the stopped state.
+    /// Unlike [`Ec2Instance::stop`] the contents of RAM are saved to the root volume and restored by [`Ec2Instance::start`],
+    /// so running processes resume where they left off.
+    /// While hibernated the ssh connection is unusable.
+    ///
+    /// The instance must have been created with [`Ec2InstanceDefinition::hibernation`](crate::Ec2InstanceDefinition::hibernation) enabled.
+    /// AWS may take a few minutes after launch before an instance is ready to hibernate.
+    pub async fn hibernate(&self) -> anyhow::Result<()> {
+        tracing::info!("Hibernating instance {}", self.instance_id);
+        audit!(
+            self.audit_log,
+            "StopInstances",
+            instance_id = self.instance_id,
+            hibernate = true
+        );
+        self.client
+            .stop_instances()
+            .instance_ids(&self.instance_id)
+            .hibernate(true)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        self.wait_for_state(InstanceStateName::Stopped).await?;
+        Ok(())
+    }
+
+    /// Terminates the instance and waits for it to reach the terminated state, leaving all other resources such as the security group in place.
+    ///
+    /// Any elastic IP is disassociated first so it can immediately be reused,
+    /// it is not released since elastic IPs are provided by the user via [`Ec2InstanceDefinition::elastic_ip_allocation_id`](crate::Ec2InstanceDefinition::elastic_ip_allocation_id).
+    ///
+    /// Returns an error if the instance has not terminated within [`AwsBuilder::poll_timeout`](crate::AwsBuilder::poll_timeout),
+    /// or [`AwsBuilder::operation_deadline`](crate::AwsBuilder::operation_deadline) if set.
+    /// The termination has still been requested, so the instance will finish terminating without further action.
+    pub async fn terminate(self) -> anyhow::Result<()> {
+        tracing::info!("Terminating instance {}", self.instance_id);
+        if let Some(allocation_id) = &self.elastic_ip_allocation_id {
+            audit!(self.audit_log, "DescribeAddresses", allocation_id);
+            let association_id = self
+                .client
+                .describe_addresses()
+                .allocation_ids(allocation_id)
+                .send()
+                .await
+                .map_err(|e| anyhow!(e.into_service_error()))?
+                .addresses()
+                .and_then(|addresses| addresses.first())
+                .filter(|address| address.instance_id() == Some(&self.instance_id))
+                .and_then(|address| address.association_id())
+                .map(|id| id.to_owned());
+            if let Some(association_id) = association_id {
+                audit!(self.audit_log, "DisassociateAddress", association_id);
+                self.client
+                    .disassociate_address()
+                    .association_id(association_id)
+                    .send()
                     .await
+                    .map_err(|e| anyhow!(e.into_service_error()))?;
+            }
+        }
+
+        audit!(
+            self.audit_log,
+            "TerminateInstances",
+            instance_id = self.instance_id
+        );
+        self.client
+            .terminate_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        self.wait_for_state(InstanceStateName::Terminated)
+            .await
+            .map_err(|e| e.context("Termination was requested but did not complete"))?;
+        Ok(())
+    }
+
+    /// Starts a previously stopped or hibernated instance, waits for it to reach the running state and reestablishes the ssh connection.
+    /// A hibernated instance resumes with the contents of its RAM restored, but its previous ssh connection was lost so is replaced.
+    ///
+    /// AWS usually assigns a new public IP when an instance is started, so [`Ec2Instance::public_ip`] is refreshed.
+    /// Instances with an associated elastic IP keep their address across a stop/start.
+    pub async fn start(&mut self) -> anyhow::Result<()> {
+        tracing::info!("Starting instance {}", self.instance_id);
+        audit!(
+            self.audit_log,
+            "StartInstances",
+            instance_id = self.instance_id
+        );
+        self.client
+            .start_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        self.refresh_connection().await
+    }
+
+    /// Looks up the current IP addresses of the instance and reconnects over ssh.
+    /// Waits for the instance to be running if it is not already.
+    ///
+    /// The instance's ssh host key is verified against the key injected when it was created rather than its address,
+    /// so this works even if the public IP has changed, e.g. after the instance was stopped and started outside of aws-throwaway.
+    pub async fn refresh_connection(&mut self) -> anyhow::Result<()> {
+        let (public_ip, private_ip) = self.wait_for_state(InstanceStateName::Running).await?;
+        self.public_ip = public_ip;
+        if let Some(private_ip) = private_ip {
+            self.private_ip = private_ip;
+        }
+        self.ssh = connect_ssh(
+            self.connect_ip(),
+            &self.credentials,
+            self.ssh_connect_timeout,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Reboots the instance and waits for ssh to become usable again.
+    /// Returns an error if the instance is not accepting ssh commands again within `timeout`.
+    pub async fn reboot(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        tracing::info!("Rebooting instance {}", self.instance_id);
+        let deadline = Instant::now() + timeout;
+        audit!(
+            self.audit_log,
+            "RebootInstances",
+            instance_id = self.instance_id
+        );
+        self.client
+            .reboot_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+
+        // The reboot is performed asynchronously so wait for the existing connection to drop before reconnecting.
+        // Otherwise we could end up reconnecting to the instance before it has gone down.
+        while self.ssh.is_alive().await {
+            if Instant::now() > deadline {
+                bail!(
+                    "Instance {} did not go down within {timeout:?} of requesting a reboot",
+                    self.instance_id
+                );
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        self.ssh = connect_ssh(
+            self.connect_ip(),
+            &self.credentials,
+            deadline.saturating_duration_since(Instant::now()),
+        )
+        .await
+        .map_err(|e| {
+            e.context(format!(
+                "Instance {} did not accept ssh connections within {timeout:?} of requesting a reboot",
+                self.instance_id
+            ))
+        })?;
+        self.ssh.shell_checked("true").await?;
+        Ok(())
+    }
+
+    /// Adds tags to the instance and its volumes after it was created, e.g. to attribute its cost to a git commit only known once the instance is running.
+    /// Tags with an existing key are overwritten.
+    /// Keys used internally by aws-throwaway for cleanup are rejected.
+    pub async fn add_tags(&mut self, tags: &[(&str, &str)]) -> anyhow::Result<()> {
+        if let Some((key, _)) = tags.iter().find(|(key, _)| key.starts_with(TAG_PREFIX)) {
+            bail!("The tag key {key:?} is reserved for use by aws-throwaway");
+        }
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        let instance = self.describe().await?;
+        let mut resources = vec![self.instance_id.clone()];
+        resources.extend(
+            instance
+                .block_device_mappings()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|mapping| mapping.ebs().and_then(|ebs| ebs.volume_id()))
+                .map(|id| id.to_owned()),
+        );
+        audit!(self.audit_log, "CreateTags", resources = ?resources, tags = ?tags);
+        self.client
+            .create_tags()
+            .set_resources(Some(resources))
+            .set_tags(Some(
+                tags.iter()
+                    .map(|(key, value)| Tag::builder().key(*key).value(*value).build())
+                    .collect(),
+            ))
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+
+        for (key, value) in tags {
+            match self.tags.iter_mut().find(|(existing, _)| existing == key) {
+                Some(existing) => existing.1 = value.to_string(),
+                None => self.tags.push((key.to_string(), value.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    async fn describe(&self) -> anyhow::Result<Instance> {
+        audit!(
+            self.audit_log,
+            "DescribeInstances",
+            instance_id = self.instance_id
+        );
+        let output = self
+            .client
+            .describe_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        output
+            .reservations()
+            .and_then(|reservations| reservations.first())
+            .and_then(|reservation| reservation.instances())
+            .and_then(|instances| instances.first())
+            .cloned()
+            .ok_or_else(|| anyhow!("Instance {} no longer exists", self.instance_id))
+    }
+
+    /// Grows the root EBS volume of the instance to `new_size_gb` and extends the root partition and filesystem to fill it,
+    /// without needing to stop or relaunch the instance.
+    ///
+    /// `new_size_gb` must be larger than the current size, EBS volumes cannot be shrunk.
+    /// AWS only allows modifying a volume once every 6 hours so this can only be called once in that period.
+    pub async fn grow_root_volume(&mut self, new_size_gb: u32) -> anyhow::Result<()> {
+        if let Some(root) = self.block_devices.first() {
+            if new_size_gb <= root.size_gb {
+                bail!(
+                    "new_size_gb {new_size_gb} must be larger than the current root volume size of {}GB",
+                    root.size_gb
+                );
+            }
+        }
+
+        let instance = self.describe().await?;
+        let root_device_name = instance.root_device_name().unwrap_or_default();
+        let volume_id = instance
+            .block_device_mappings()
+            .unwrap_or_default()
+            .iter()
+            .find(|mapping| mapping.device_name() == Some(root_device_name))
+            .and_then(|mapping| mapping.ebs())
+            .and_then(|ebs| ebs.volume_id())
+            .ok_or_else(|| anyhow!("Instance {} has no root EBS volume", self.instance_id))?
+            .to_owned();
+
+        tracing::info!(
+            "Growing root volume {volume_id} of instance {} to {new_size_gb}GB",
+            self.instance_id
+        );
+        audit!(self.audit_log, "ModifyVolume", volume_id, new_size_gb);
+        self.client
+            .modify_volume()
+            .volume_id(&volume_id)
+            .size(new_size_gb as i32)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+
+        // The filesystem can be extended as soon as the modification reaches the optimizing state,
+        // there is no need to wait for the optimization to complete.
+        let deadline = Instant::now() + Duration::from_secs(300);
+        loop {
+            audit!(self.audit_log, "DescribeVolumesModifications", volume_id);
+            let modifications = self
+                .client
+                .describe_volumes_modifications()
+                .volume_ids(&volume_id)
+                .send()
+                .await
+                .map_err(|e| anyhow!(e.into_service_error()))?;
+            let modification = modifications
+                .volumes_modifications()
+                .and_then(|modifications| modifications.first());
+            match modification.and_then(|m| m.modification_state()) {
+                Some(VolumeModificationState::Optimizing | VolumeModificationState::Completed) => {
+                    break
+                }
+                Some(VolumeModificationState::Failed) => bail!(
+                    "Modifying volume {volume_id} failed: {}",
+                    modification
+                        .and_then(|m| m.status_message())
+                        .unwrap_or_default()
+                ),
+                _ => {}
+            }
+            if Instant::now() > deadline {
+                bail!("Volume {volume_id} was not resized within 300s");
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+
+        // `findmnt -o SOURCE /` reports `/dev/root` on images that boot without an initramfs, such as Ubuntu's,
+        // which is not a real device node, so the partition is instead found via the device number of the root mount.
+        let mountinfo = self
+            .ssh
+            .shell_checked("cat /proc/self/mountinfo")
+            .await?
+            .stdout;
+        let root = RootMount::from_mountinfo(&mountinfo)
+            .ok_or_else(|| anyhow!("Failed to find the root mount in /proc/self/mountinfo"))?;
+        let grow_filesystem = match root.fstype.as_str() {
+            "xfs" => "sudo xfs_growfs /",
+            _ => r#"sudo resize2fs "$ROOT""#,
+        };
+        let maj_min = &root.maj_min;
+        // growpart exits with 1 when the partition already fills the disk
+        self.ssh
+            .shell_checked(&format!(
+                r#"set -e
+if [ -e /sys/dev/block/{maj_min} ]; then
+    SYS=$(readlink -f /sys/dev/block/{maj_min})
+else
+    # e.g. btrfs reports an anonymous device number, so fall back to the device named by the kernel's root= parameter
+    DEV=$(sed -n 's/.*\broot=\([^ ]*\).*/\1/p' /proc/cmdline)
+    case $DEV in /dev/*) ;; *) DEV=$(findfs "$DEV") ;; esac
+    SYS=$(readlink -f /sys/class/block/$(basename "$(readlink -f "$DEV")"))
+fi
+ROOT=/dev/$(basename "$SYS")
+DISK=/dev/$(basename "$(dirname "$SYS")")
+PART=$(cat "$SYS/partition")
+sudo growpart "$DISK" "$PART" || [ $? -eq 1 ]
+{grow_filesystem}"#
+            ))
+            .await
+            .map_err(|e| e.context("Failed to extend the root filesystem"))?;
+
+        if let Some(root) = self.block_devices.first_mut() {
+            root.size_gb = new_size_gb;
+        }
+        Ok(())
+    }
+
+    /// Fetches the serial console output of the instance, useful for diagnosing boot or cloud-init failures when ssh is unavailable.
+    /// AWS only populates the console output a few minutes after the instance boots, until then an empty string is returned.
+    pub async fn console_output(&self) -> anyhow::Result<String> {
+        audit!(
+            self.audit_log,
+            "GetConsoleOutput",
+            instance_id = self.instance_id
+        );
+        let output = self
+            .client
+            .get_console_output()
+            .instance_id(&self.instance_id)
+            .send()
+            .await
+            .map_err(|e| anyhow!(e.into_service_error()))?;
+        match output.output() {
+            Some(encoded) => {
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|e| anyhow!(e).context("Failed to decode console output"))?;
+                Ok(String::from_utf8_lossy(&decoded).into_owned())
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Waits until AWS reports both the instance and system status checks as passed.
+    /// The status checks are still initializing for a few minutes after the instance is first reachable over ssh,
+    /// call this before running workloads that are sensitive to the instance not yet being fully healthy.
+    ///
+    /// Returns an error if the checks have not passed within `timeout`.
+    pub async fn wait_for_status_checks(&self, timeout: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            audit!(
+                self.audit_log,
+                "DescribeInstanceStatus",
+                instance_id = self.instance_id
+            );
+            let statuses = self
+                .client
+                .describe_instance_status()
+                .instance_ids(&self.instance_id)
+                .include_all_instances(true)
+                .send()
+                .await
+                .map_err(|e| anyhow!(e.into_service_error()))?;
+            let status = statuses
+                .instance_statuses()
+                .and_then(|statuses| statuses.first());
+            let instance_status = status
+                .and_then(|x| x.instance_status())
+                .and_then(|x| x.status());
+            let system_status = status
+                .and_then(|x| x.system_status())
+                .and_then(|x| x.status());
+            if instance_status == Some(&SummaryStatus::Ok)
+                && system_status == Some(&SummaryStatus::Ok)
+            {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "Status checks for instance {} did not pass within {timeout:?}, instance status: {instance_status:?}, system status: {system_status:?}",
+                    self.instance_id
+                );
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Polls the instance until it reaches `state`, returning the public and private IPs it has in that state.
+    /// When waiting for a running instance this also waits for the public IP to be assigned.
+    ///
+    /// Fails if `state` is not reached within the state change timeout, or if the instance is terminated while waiting for any other state.
+    async fn wait_for_state(
+        &self,
+        state: InstanceStateName,
+    ) -> anyhow::Result<(Option<IpAddr>, Option<IpAddr>)> {
+        let deadline = Instant::now() + self.state_change_timeout;
+        let parse_ip = |ip: Option<&str>| -> anyhow::Result<Option<IpAddr>> {
+            ip.map(|ip| {
+                ip.parse()
+                    .map_err(|e| anyhow!("AWS returned the invalid IP {ip:?}: {e}"))
+            })
+            .transpose()
+        };
+        loop {
+            audit!(
+                self.audit_log,
+                "DescribeInstances",
+                instance_id = self.instance_id
+            );
+            for reservation in self
+                .client
+                .describe_instances()
+                .instance_ids(&self.instance_id)
+                .send()
+                .await
+                .map_err(|e| anyhow!(e.into_service_error()))?
+                .reservations()
+                .unwrap_or_default()
+            {
+                for instance in reservation.instances().unwrap_or_default() {
+                    let public_ip = parse_ip(instance.public_ip_address())?;
+                    let private_ip = parse_ip(instance.private_ip_address())?;
+                    let current_state = instance.state().and_then(|x| x.name());
+                    if state != InstanceStateName::Terminated
+                        && matches!(
+                            current_state,
+                            Some(InstanceStateName::ShuttingDown | InstanceStateName::Terminated)
+                        )
                     {
-                        Err(err) => {
-                            // 3.
-                            tracing::info!("Failed to make ssh connection to server, the host has probably not run its user-data script yet, retrying, error was: {err:?}");
-                            tokio::time::sleep_until(start + Duration::from_secs(1)).await;
-                            continue;
-                        }
-                        // 4. Then finally we have a working ssh connection.
-                        Ok(ssh) => {
-                            break Ec2Instance {
-                                ssh,
-                                public_ip,
-                                private_ip,
-                                client_private_key: client_private_key.to_owned(),
-                            };
-                        }
-                    };
+                        bail!(
+                            "Instance {} was terminated while waiting for it to become {}",
+                            self.instance_id,
+                            state.as_str()
+                        );
+                    }
+                    if current_state == Some(&state)
+                        && (state != InstanceStateName::Running
+                            || !self.connect_via_public_ip
+                            || public_ip.is_some())
+                    {
+                        return Ok((public_ip, private_ip));
+                    }
+                }
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "Instance {} did not become {} within {:?}",
+                    self.instance_id,
+                    state.as_str(),
+                    self.state_change_timeout
+                );
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    pub(crate) async fn new(
+        launched: LaunchedInstance,
+        credentials: SshCredentials,
+    ) -> Result<Self, Ec2InstanceError> {
+        let connect_ip = match launched.public_ip {
+            Some(public_ip) if launched.connect_via_public_ip => public_ip,
+            _ => launched.private_ip,
+        };
+        let ssh_start = Instant::now();
+        let ssh = connect_ssh(connect_ip, &credentials, launched.ssh_connect_timeout)
+            .await
+            .map_err(|source| Ec2InstanceError::SshConnectTimeout {
+                instance_id: launched.instance_id.clone(),
+                source,
+            })?;
+        let mut creation_timings = launched.creation_timings;
+        if let Some(timings) = &mut creation_timings {
+            timings.ssh_connect = ssh_start.elapsed();
+            tracing::info!(
+                instance_id = launched.instance_id,
+                launch_secs = timings.launch.as_secs_f64(),
+                ip_assignment_secs = timings.ip_assignment.as_secs_f64(),
+                ssh_connect_secs = timings.ssh_connect.as_secs_f64(),
+                "instance {} is ready",
+                launched.instance_id
+            );
+        }
+        Ok(Ec2Instance {
+            client: launched.client,
+            audit_log: launched.audit_log,
+            instance_id: launched.instance_id,
+            public_ip: launched.public_ip,
+            private_ip: launched.private_ip,
+            private_dns_name: launched.private_dns_name,
+            connect_via_public_ip: launched.connect_via_public_ip,
+            credentials,
+            placement: launched.placement,
+            tags: launched.tags,
+            block_devices: launched.block_devices,
+            network_interfaces: launched.network_interfaces,
+            elastic_ip_allocation_id: launched.elastic_ip_allocation_id,
+            elastic_ip: launched.elastic_ip,
+            creation_timings,
+            ssh_connect_timeout: launched.ssh_connect_timeout,
+            state_change_timeout: launched.state_change_timeout,
+            ssh,
+        })
+    }
+}
+
+/// The keys required to connect to an instance over ssh, obtained via [`Ec2Instance::keys`].
+/// Anyone with these keys can access the instance, so store them securely.
+#[derive(Clone, Debug)]
+pub struct InstanceKeys {
+    /// The user to login as
+    pub user: String,
+    /// The OpenSSH formatted private key used to authenticate with the instance
+    pub client_private_key: String,
+    /// The OpenSSH formatted public host key of the instance, used to verify the instance's identity
+    pub host_public_key: String,
+}
+
+/// How long to keep retrying after the ssh server first rejects our key.
+/// Rejections are expected briefly while cloud-init writes authorized_keys during boot, but persistent rejection means the key or user is wrong.
+const AUTH_REJECTED_RETRY_WINDOW: Duration = Duration::from_secs(60);
+
+/// Repeatedly attempts to connect to the instance over ssh until it succeeds or `timeout` elapses.
+async fn connect_ssh(
+    address: IpAddr,
+    credentials: &SshCredentials,
+    timeout: Duration,
+) -> anyhow::Result<SshConnection> {
+    let deadline = Instant::now() + timeout;
+    let mut last_error = String::new();
+    let mut auth_rejected_since = None;
+    let mut jump_host = None;
+    loop {
+        if Instant::now() > deadline {
+            bail!("Failed to establish an ssh connection to {address} within {timeout:?}, the last error was: {last_error}");
+        }
+        let start = Instant::now();
+        // The whole attempt runs under the deadline so that a server that accepts the TCP connection but stalls the handshake can not hang us forever.
+        let attempt = async {
+            let connect = async {
+                if credentials.jump_host.is_some() {
+                    // The jump host is connected lazily so that connecting to it is also bounded by the deadline.
+                    if jump_host.is_none() {
+                        jump_host = SshConnection::connect_jump_host(credentials).await?;
+                    }
+                    let tunnel = jump_host.as_ref().unwrap().tunnel_ssh(address).await;
+                    if tunnel.is_err() {
+                        // The jump host connection may have dropped, so reconnect to it on the next attempt.
+                        jump_host = None;
+                    }
+                    tunnel
+                } else {
+                    match TcpStream::connect((address, 22)).await {
+                        Ok(stream) => Ok(Box::new(stream) as Box<dyn SshStream>),
+                        Err(e) => Err(e.into()),
+                    }
                 }
             };
+            let stream = match tokio::time::timeout(Duration::from_secs(10), connect).await {
+                Err(_) => return Err(SshAttemptError::TcpTimeout),
+                Ok(Err(e)) => return Err(SshAttemptError::Tcp(e)),
+                Ok(Ok(stream)) => stream,
+            };
+            SshConnection::new(stream, address, credentials, jump_host.clone())
+                .await
+                .map_err(SshAttemptError::Ssh)
+        };
+        let Ok(result) = tokio::time::timeout_at(deadline, attempt).await else {
+            bail!("Failed to establish an ssh connection to {address} within {timeout:?}, the last attempt did not complete in time, the previous error was: {last_error}");
+        };
+        // We retry many times before we are able to succesfully make an ssh connection.
+        // Each error is expected and so is logged as a `info!` that describes the underlying startup process that is supposed to cause the error.
+        // A numbered comment is left before each `info!` to demonstrate the order each error occurs in.
+        match result {
+            Err(SshAttemptError::TcpTimeout) => {
+                // 1.
+                tracing::info!("Timed out connecting to {address} over ssh, the host is probably not accessible yet, retrying");
+                last_error = "timed out opening a TCP connection".to_owned();
+            }
+            Err(SshAttemptError::Tcp(e)) => {
+                // 2.
+                tracing::info!("failed to connect to {address}:22, the host probably hasnt started their ssh service yet, retrying, error was {e}");
+                last_error = e.to_string();
+                tokio::time::sleep_until(start + Duration::from_secs(1)).await;
+            }
+            Err(SshAttemptError::Ssh(SshConnectError::Fatal(err))) => return Err(err),
+            Err(SshAttemptError::Ssh(SshConnectError::Other(err))) => {
+                // 3.
+                tracing::info!("Failed to make ssh connection to server, the host has probably not run its user-data script yet, retrying, error was: {err:?}");
+                last_error = format!("{err:?}");
+                tokio::time::sleep_until(start + Duration::from_secs(1)).await;
+            }
+            Err(SshAttemptError::Ssh(SshConnectError::AuthRejected)) => {
+                // 4.
+                let since = *auth_rejected_since.get_or_insert(start);
+                if since.elapsed() > AUTH_REJECTED_RETRY_WINDOW {
+                    bail!("The ssh server at {address} kept rejecting authentication for {AUTH_REJECTED_RETRY_WINDOW:?}, the wrong private key or user is probably in use");
+                }
+                tracing::info!("ssh server rejected authentication, the host has probably not finished setting up authorized_keys yet, retrying");
+                last_error = "authentication was rejected".to_owned();
+                tokio::time::sleep_until(start + Duration::from_secs(1)).await;
+            }
+            // 5. Then finally we have a working ssh connection.
+            Ok(ssh) => return Ok(ssh),
         }
     }
 }
+
+/// The root filesystem of an instance, as described by `/proc/self/mountinfo`
+#[derive(Debug, PartialEq)]
+struct RootMount {
+    /// The device number of the root filesystem, e.g. `259:1`
+    maj_min: String,
+    /// e.g. `ext4` or `xfs`
+    fstype: String,
+}
+
+impl RootMount {
+    /// Each line of mountinfo is formatted as
+    /// `ID PARENT_ID MAJ:MIN ROOT MOUNT_POINT OPTIONS [OPTIONAL_FIELDS...] - FSTYPE SOURCE SUPER_OPTIONS`.
+    /// If `/` is mounted over multiple times the last mount is the one that is visible.
+    fn from_mountinfo(mountinfo: &str) -> Option<RootMount> {
+        mountinfo
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let maj_min = *fields.get(2)?;
+                let (major, minor) = maj_min.split_once(':')?;
+                let is_number = |x: &str| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit());
+                if fields.get(4) != Some(&"/") || !is_number(major) || !is_number(minor) {
+                    return None;
+                }
+                let separator = fields.iter().position(|field| *field == "-")?;
+                Some(RootMount {
+                    maj_min: maj_min.to_owned(),
+                    fstype: (*fields.get(separator + 1)?).to_owned(),
+                })
+            })
+            .last()
+    }
+}
+
+/// The ways a single attempt made by [`connect_ssh`] can fail
+enum SshAttemptError {
+    /// Opening the TCP connection did not complete in time
+    TcpTimeout,
+    /// Opening the TCP connection failed
+    Tcp(anyhow::Error),
+    /// The TCP connection was opened but establishing the ssh session failed
+    Ssh(SshConnectError),
+}
+
+/// Describes an upcoming interruption reported by [`Ec2Instance::termination_watcher`]
+#[derive(Debug, Clone)]
+pub enum TerminationNotice {
+    /// The spot instance is about to be interrupted.
+    /// Contains the JSON reported by the metadata service, e.g. `{"action": "terminate", "time": "2017-09-18T08:22:00Z"}`
+    SpotInterruption(String),
+    /// One or more maintenance events are scheduled for the instance.
+    /// Contains the JSON list of events reported by the metadata service.
+    ScheduledMaintenance(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_mount_of_dev_root() {
+        // Ubuntu boots without an initramfs so the root filesystem is reported as /dev/root
+        let mountinfo = "\
+24 29 0:22 / /sys rw,nosuid,nodev,noexec,relatime shared:7 - sysfs sysfs rw
+25 29 0:23 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+29 1 259:1 / / rw,relatime shared:1 - ext4 /dev/root rw,discard,errors=remount-ro
+30 29 259:3 / /boot/efi rw,relatime shared:2 - vfat /dev/nvme0n1p15 rw
+";
+        assert_eq!(
+            RootMount::from_mountinfo(mountinfo),
+            Some(RootMount {
+                maj_min: "259:1".to_owned(),
+                fstype: "ext4".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn root_mount_of_xfs_without_optional_fields() {
+        let mountinfo = "\
+20 1 202:1 / / rw,noatime - xfs /dev/xvda1 rw,attr2,inode64,noquota
+21 20 0:5 / /dev rw,nosuid - devtmpfs devtmpfs rw
+";
+        assert_eq!(
+            RootMount::from_mountinfo(mountinfo),
+            Some(RootMount {
+                maj_min: "202:1".to_owned(),
+                fstype: "xfs".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn root_mount_missing() {
+        assert_eq!(RootMount::from_mountinfo(""), None);
+        assert_eq!(
+            RootMount::from_mountinfo("21 20 0:5 / /dev rw,nosuid - devtmpfs devtmpfs rw"),
+            None
+        );
+    }
+}
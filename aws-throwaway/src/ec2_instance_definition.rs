@@ -0,0 +1,667 @@
+use crate::cpu_arch::get_arch_of_instance_type;
+use crate::{CpuArch, Ec2InstanceError, TAG_PREFIX};
+use aws_sdk_ec2::types::{HostnameType, HttpTokensState, InstanceType, Tenancy, VolumeType};
+use std::time::Duration;
+
+/// Defines an instance that can be launched via [`Aws::create_ec2_instance`](crate::Aws::create_ec2_instance)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ec2InstanceDefinition {
+    pub(crate) instance_type: InstanceType,
+    pub(crate) os: InstanceOs,
+    pub(crate) ubuntu_image_variant: UbuntuImageVariant,
+    pub(crate) ubuntu_image_stream: UbuntuImageStream,
+    pub(crate) ubuntu_image_serial: Option<String>,
+    pub(crate) ssh_user: Option<String>,
+    pub(crate) volume_size_gb: u32,
+    pub(crate) volume_type: VolumeType,
+    pub(crate) volume_iops: Option<i32>,
+    pub(crate) volume_iops_per_gb: Option<u32>,
+    pub(crate) volume_throughput: Option<i32>,
+    pub(crate) volume_encryption: Option<Option<String>>,
+    pub(crate) ebs_optimized: Option<bool>,
+    pub(crate) iam_instance_profile: Option<String>,
+    pub(crate) existing_key_pair: Option<ExistingKeyPair>,
+    pub(crate) additional_volumes: Vec<BlockDevice>,
+    pub(crate) elastic_ip_allocation_id: Option<String>,
+    pub(crate) private_dns_hostname_type: Option<HostnameType>,
+    pub(crate) root_device_name: Option<String>,
+    pub(crate) user_data: Option<String>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) connect_via_public_ip: Option<bool>,
+    pub(crate) tenancy: Option<Tenancy>,
+    pub(crate) cpu_arch: Option<CpuArch>,
+    pub(crate) metadata_http_tokens: HttpTokensState,
+    pub(crate) metadata_hop_limit: u32,
+    pub(crate) capacity_reservation_id: Option<String>,
+    pub(crate) ipv6_address_count: Option<u32>,
+    pub(crate) detailed_monitoring: bool,
+    pub(crate) hibernation: bool,
+    pub(crate) auto_terminate_after: Option<Duration>,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+impl Ec2InstanceDefinition {
+    /// Start defining an instance with the specified instance type
+    pub fn new(instance_type: InstanceType) -> Self {
+        Ec2InstanceDefinition {
+            instance_type,
+            os: InstanceOs::Ubuntu22_04,
+            ubuntu_image_variant: UbuntuImageVariant::Server,
+            ubuntu_image_stream: UbuntuImageStream::Stable,
+            ubuntu_image_serial: None,
+            ssh_user: None,
+            volume_size_gb: 8,
+            volume_type: VolumeType::Gp3,
+            volume_iops: None,
+            volume_iops_per_gb: None,
+            volume_throughput: None,
+            volume_encryption: None,
+            ebs_optimized: None,
+            iam_instance_profile: None,
+            existing_key_pair: None,
+            additional_volumes: vec![],
+            elastic_ip_allocation_id: None,
+            private_dns_hostname_type: None,
+            root_device_name: None,
+            user_data: None,
+            hostname: None,
+            connect_via_public_ip: None,
+            tenancy: None,
+            cpu_arch: None,
+            metadata_http_tokens: HttpTokensState::Required,
+            metadata_hop_limit: 1,
+            capacity_reservation_id: None,
+            ipv6_address_count: None,
+            detailed_monitoring: false,
+            hibernation: false,
+            auto_terminate_after: None,
+            tags: vec![],
+        }
+    }
+
+    /// Sets the operating system the instance is launched with.
+    /// Defaults to [`InstanceOs::Ubuntu22_04`].
+    pub fn os(mut self, os: InstanceOs) -> Self {
+        self.os = os;
+        self
+    }
+
+    /// Overrides the user that aws-throwaway logs in as over ssh.
+    /// The user must be allowed to login with the instance's key pair and must have passwordless sudo.
+    /// Defaults to the default user of the [`InstanceOs`], e.g. `ubuntu` or `ec2-user`.
+    pub fn ssh_user(mut self, user: impl Into<String>) -> Self {
+        self.ssh_user = Some(user.into());
+        self
+    }
+
+    /// Selects which variant of the Ubuntu image to launch, only valid with an Ubuntu [`InstanceOs`].
+    /// Defaults to [`UbuntuImageVariant::Server`].
+    pub fn ubuntu_image_variant(mut self, variant: UbuntuImageVariant) -> Self {
+        self.ubuntu_image_variant = variant;
+        self
+    }
+
+    /// Selects which release stream the Ubuntu image is taken from, only valid with an Ubuntu [`InstanceOs`].
+    /// Defaults to [`UbuntuImageStream::Stable`].
+    pub fn ubuntu_image_stream(mut self, stream: UbuntuImageStream) -> Self {
+        self.ubuntu_image_stream = stream;
+        self
+    }
+
+    /// Pins the Ubuntu image to a specific build serial, e.g. `20230516` or `20230516.1`, instead of the latest image of the stream.
+    /// Pinning makes runs reproducible, but old serials are eventually removed by Canonical.
+    /// Only valid with an Ubuntu [`InstanceOs`].
+    pub fn ubuntu_image_serial(mut self, serial: impl Into<String>) -> Self {
+        self.ubuntu_image_serial = Some(serial.into());
+        self
+    }
+
+    /// Sets the size of the root volume in gigabytes.
+    /// Defaults to 8GB.
+    pub fn volume_size_gigabytes(mut self, size_gb: u32) -> Self {
+        self.volume_size_gb = size_gb;
+        self
+    }
+
+    /// Overrides the device name of the root volume, this must match the root device name of the AMI or the
+    /// root volume settings such as [`Ec2InstanceDefinition::volume_size_gigabytes`] will be ignored.
+    /// Defaults to the root device name of the AMI used for the [`InstanceOs`].
+    pub fn root_device_name(mut self, device_name: impl Into<String>) -> Self {
+        self.root_device_name = Some(device_name.into());
+        self
+    }
+
+    /// Sets the type of the root volume.
+    /// Defaults to [`VolumeType::Gp3`], which provides a baseline of 3000 IOPS and 125 MiB/s regardless of size unless overridden via
+    /// [`Ec2InstanceDefinition::volume_iops`] or [`Ec2InstanceDefinition::volume_throughput`].
+    ///
+    /// Previously the default was [`VolumeType::Gp2`], whose performance scales with the size of the volume,
+    /// set it explicitly to keep the old behaviour.
+    pub fn volume_type(mut self, volume_type: VolumeType) -> Self {
+        self.volume_type = volume_type;
+        self
+    }
+
+    /// Sets the provisioned IOPS of the root volume.
+    /// Only supported by the `gp3`, `io1` and `io2` volume types.
+    pub fn volume_iops(mut self, iops: i32) -> Self {
+        self.volume_iops = Some(iops);
+        self
+    }
+
+    /// Sets the provisioned IOPS of the root volume to `ratio` IOPS per gigabyte of [`Ec2InstanceDefinition::volume_size_gigabytes`],
+    /// clamped to the minimum and maximum IOPS of the volume type.
+    /// This keeps the IOPS proportional to the volume size as the size is changed.
+    ///
+    /// Only supported by the `gp3`, `io1` and `io2` volume types and cannot be combined with [`Ec2InstanceDefinition::volume_iops`].
+    pub fn iops_per_gb(mut self, ratio: u32) -> Self {
+        self.volume_iops_per_gb = Some(ratio);
+        self
+    }
+
+    /// Sets the provisioned throughput of the root volume in MiB/s.
+    /// Only supported by the `gp3` volume type.
+    pub fn volume_throughput(mut self, throughput: i32) -> Self {
+        self.volume_throughput = Some(throughput);
+        self
+    }
+
+    /// Encrypts the root volume and all additional volumes with the KMS key `kms_key_id`, which may be a key id, alias or ARN.
+    /// When `kms_key_id` is `None` the account's default EBS encryption key is used.
+    /// Defaults to unencrypted volumes, unless the account enables EBS encryption by default.
+    pub fn volume_encryption(mut self, kms_key_id: Option<String>) -> Self {
+        self.volume_encryption = Some(kms_key_id);
+        self
+    }
+
+    /// Explicitly enables or disables EBS optimization.
+    /// When not set the AWS default for the instance type is used, which is enabled for most modern instance types.
+    /// Enabling EBS optimization on an instance type that does not support it is rejected by [`Aws::create_ec2_instance`](crate::Aws::create_ec2_instance).
+    pub fn ebs_optimized(mut self, ebs_optimized: bool) -> Self {
+        self.ebs_optimized = Some(ebs_optimized);
+        self
+    }
+
+    /// Attaches an IAM instance profile to the instance, allowing code running on the instance to call AWS APIs with the profile's role.
+    /// Accepts either the name or the ARN of the instance profile.
+    ///
+    /// An instance profile that was only just created can take several seconds to propagate,
+    /// until then AWS will reject launching an instance with it.
+    pub fn iam_instance_profile(mut self, name_or_arn: impl Into<String>) -> Self {
+        self.iam_instance_profile = Some(name_or_arn.into());
+        self
+    }
+
+    /// Launch the instance with an existing EC2 key pair instead of the key pair created by [`Aws`](crate::Aws).
+    /// `private_key` must be the OpenSSH formatted private key of the key pair, it is used by aws-throwaway to ssh into the instance.
+    ///
+    /// The key pair is not managed by aws-throwaway and so will never be deleted during cleanup.
+    pub fn existing_key_pair(
+        mut self,
+        key_name: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Self {
+        self.existing_key_pair = Some(ExistingKeyPair {
+            key_name: key_name.into(),
+            private_key: private_key.into(),
+        });
+        self
+    }
+
+    /// Attaches an additional EBS volume to the instance at `device_name`, e.g. `/dev/sdf`.
+    /// Can be called multiple times to attach multiple volumes.
+    /// The volumes are created empty and must be formatted and mounted before use, they are deleted when the instance is terminated.
+    ///
+    /// Note that on nitro based instance types the volume will appear as an NVMe device such as `/dev/nvme1n1` instead of `device_name`.
+    pub fn add_volume(
+        mut self,
+        device_name: impl Into<String>,
+        size_gb: u32,
+        volume_type: VolumeType,
+    ) -> Self {
+        self.additional_volumes.push(BlockDevice {
+            device_name: device_name.into(),
+            size_gb,
+            volume_type,
+        });
+        self
+    }
+
+    /// Associates an existing elastic IP with the instance, it then becomes the public IP of the instance.
+    /// The elastic IP must not already be associated with another instance or network interface.
+    ///
+    /// The elastic IP is not managed by aws-throwaway and so will never be released during cleanup.
+    pub fn elastic_ip_allocation_id(mut self, allocation_id: impl Into<String>) -> Self {
+        self.elastic_ip_allocation_id = Some(allocation_id.into());
+        self
+    }
+
+    /// Sets how the private DNS hostname of the instance is derived,
+    /// [`HostnameType::ResourceName`] gives hostnames based on the instance id instead of the private IP.
+    /// Defaults to the setting of the subnet the instance is launched in.
+    pub fn private_dns_hostname_type(mut self, hostname_type: HostnameType) -> Self {
+        self.private_dns_hostname_type = Some(hostname_type);
+        self
+    }
+
+    /// Runs `script` as root with bash when the instance first boots, e.g. to install packages or configure sysctls.
+    ///
+    /// aws-throwaway uses user data to configure the ssh host key of the instance,
+    /// `script` is appended to that setup and so always runs after it.
+    /// The combined user data must not exceed AWS's limit of 16KB.
+    pub fn user_data(mut self, script: impl Into<String>) -> Self {
+        self.user_data = Some(script.into());
+        self
+    }
+
+    /// Sets the hostname of the instance during boot, before any [`Ec2InstanceDefinition::user_data`] script runs.
+    /// The hostname is also added to `/etc/hosts` so that it resolves locally, and is preserved across reboots.
+    ///
+    /// Must be a valid RFC 1123 hostname, e.g. `node-1` or `node-1.test.internal`.
+    /// Note that every instance created from this definition is given the same hostname.
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Overrides [`AwsBuilder::use_public_addresses`](crate::AwsBuilder::use_public_addresses) for this instance,
+    /// when false aws-throwaway connects to the instance via its private IP and does not wait for a public IP to be assigned.
+    pub fn connect_via_public_ip(mut self, connect_via_public_ip: bool) -> Self {
+        self.connect_via_public_ip = Some(connect_via_public_ip);
+        self
+    }
+
+    /// Configures the instance metadata service.
+    /// `http_tokens` controls whether IMDSv2 session tokens are required, use [`HttpTokensState::Optional`] to also allow IMDSv1.
+    /// `http_put_response_hop_limit` is the number of network hops a token request may take, between 1 and 64,
+    /// e.g. containers not using host networking need at least 2.
+    /// Defaults to requiring tokens with a hop limit of 1.
+    pub fn metadata_options(
+        mut self,
+        http_tokens: HttpTokensState,
+        http_put_response_hop_limit: u32,
+    ) -> Self {
+        self.metadata_http_tokens = http_tokens;
+        self.metadata_hop_limit = http_put_response_hop_limit;
+        self
+    }
+
+    /// Launches the instance into the capacity reservation with the specified id, e.g. `cr-0123456789abcdef0`,
+    /// guaranteeing capacity is available as long as the reservation is not exhausted.
+    /// The reservation must match the instance type, tenancy and platform of the instance.
+    ///
+    /// If the reservation is exhausted [`Aws::create_ec2_instance`](crate::Aws::create_ec2_instance) returns [`Ec2InstanceError::InsufficientCapacity`].
+    pub fn capacity_reservation(mut self, capacity_reservation_id: impl Into<String>) -> Self {
+        self.capacity_reservation_id = Some(capacity_reservation_id.into());
+        self
+    }
+
+    /// Assigns `count` IPv6 addresses to the primary network interface of the instance,
+    /// they are listed in [`NetworkInterface::ipv6_addresses`](crate::ec2_instance::NetworkInterface::ipv6_addresses).
+    /// The default subnet of the availability zone the instance launches into must have an IPv6 CIDR block associated with it.
+    ///
+    /// aws-throwaway still connects to the instance over IPv4.
+    pub fn ipv6_address_count(mut self, count: u32) -> Self {
+        self.ipv6_address_count = Some(count);
+        self
+    }
+
+    /// When true, CloudWatch collects metrics for the instance every minute instead of every 5 minutes.
+    /// Detailed monitoring is billed separately by AWS.
+    /// Defaults to false.
+    pub fn detailed_monitoring(mut self, enabled: bool) -> Self {
+        self.detailed_monitoring = enabled;
+        self
+    }
+
+    /// When true, the instance can be hibernated via [`Ec2Instance::hibernate`](crate::ec2_instance::Ec2Instance::hibernate),
+    /// preserving the contents of its RAM across a stop and start.
+    ///
+    /// Hibernation requires an encrypted root volume, so the root volume is encrypted when enabled,
+    /// with the key set by [`Ec2InstanceDefinition::volume_encryption`] or otherwise the default EBS key.
+    /// The root volume must be large enough to hold the instance's RAM in addition to the OS,
+    /// so [`Ec2InstanceDefinition::volume_size_gigabytes`] must be at least 8GB more than the memory of the instance type.
+    /// Defaults to false.
+    pub fn hibernation(mut self, enabled: bool) -> Self {
+        self.hibernation = enabled;
+        self
+    }
+
+    /// Terminates the instance once `ttl` has passed since it booted, rounded up to the minute,
+    /// so that an instance is not left running indefinitely if the process that created it crashes before cleaning up.
+    ///
+    /// This is only a backstop, [`Aws::cleanup_resources`](crate::Aws::cleanup_resources) must still be called to remove the instance and the other resources created by aws-throwaway.
+    /// The scheduled shutdown is cancelled if the instance is rebooted, stopped or hibernated.
+    pub fn auto_terminate_after(mut self, ttl: Duration) -> Self {
+        self.auto_terminate_after = Some(ttl);
+        self
+    }
+
+    /// Runs the instance on dedicated hardware with [`Tenancy::Dedicated`] or on a dedicated host with [`Tenancy::Host`].
+    /// Defaults to shared hardware.
+    ///
+    /// Host tenancy cannot be combined with [`AwsBuilder::placement_strategy`](crate::AwsBuilder::placement_strategy)
+    /// and dedicated tenancy cannot be combined with [`PlacementStrategy::Spread`](crate::PlacementStrategy::Spread).
+    pub fn tenancy(mut self, tenancy: Tenancy) -> Self {
+        self.tenancy = Some(tenancy);
+        self
+    }
+
+    /// Overrides the CPU architecture used to select the AMI.
+    /// By default the architecture is detected from the instance type name, this is only needed if detection fails for a new instance type.
+    pub fn cpu_arch(mut self, cpu_arch: CpuArch) -> Self {
+        self.cpu_arch = Some(cpu_arch);
+        self
+    }
+
+    /// Adds a tag to the instance and its volumes, can be called multiple times to add multiple tags.
+    /// Setting the `Name` tag replaces the default name of `aws-throwaway`.
+    /// Keys used internally by aws-throwaway for cleanup are rejected by [`Aws::create_ec2_instance`](crate::Aws::create_ec2_instance).
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Ec2InstanceError> {
+        self.resolved_cpu_arch()?;
+        if !(1..=64).contains(&self.metadata_hop_limit) {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "http_put_response_hop_limit was set to {} but must be between 1 and 64",
+                self.metadata_hop_limit
+            )));
+        }
+        if self.volume_iops.is_some()
+            && !matches!(
+                self.volume_type,
+                VolumeType::Gp3 | VolumeType::Io1 | VolumeType::Io2
+            )
+        {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "volume_iops was set but volume type {:?} does not support provisioned IOPS",
+                self.volume_type
+            )));
+        }
+        if let Some(ratio) = self.volume_iops_per_gb {
+            if self.volume_iops.is_some() {
+                return Err(Ec2InstanceError::InvalidDefinition(
+                    "volume_iops and iops_per_gb cannot both be set".to_owned(),
+                ));
+            }
+            match iops_limits(&self.volume_type) {
+                Some(limits) if ratio > limits.max_per_gb => {
+                    return Err(Ec2InstanceError::InvalidDefinition(format!(
+                        "iops_per_gb was set to {ratio} but volume type {:?} supports at most {} IOPS per GB",
+                        self.volume_type, limits.max_per_gb
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(Ec2InstanceError::InvalidDefinition(format!(
+                        "iops_per_gb was set but volume type {:?} does not support provisioned IOPS",
+                        self.volume_type
+                    )));
+                }
+            }
+        }
+        if self.volume_throughput.is_some() && self.volume_type != VolumeType::Gp3 {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "volume_throughput was set but volume type {:?} does not support provisioned throughput",
+                self.volume_type
+            )));
+        }
+        for (i, volume) in self.additional_volumes.iter().enumerate() {
+            if volume.device_name == self.resolved_root_device_name()
+                || self.additional_volumes[..i]
+                    .iter()
+                    .any(|x| x.device_name == volume.device_name)
+            {
+                return Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "The device name {:?} is used by multiple volumes",
+                    volume.device_name
+                )));
+            }
+        }
+        if let Some(hostname) = &self.hostname {
+            if !is_valid_hostname(hostname) {
+                return Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "The hostname {hostname:?} is not a valid RFC 1123 hostname"
+                )));
+            }
+        }
+        if self.os != InstanceOs::Ubuntu22_04
+            && (self.ubuntu_image_variant != UbuntuImageVariant::Server
+                || self.ubuntu_image_stream != UbuntuImageStream::Stable
+                || self.ubuntu_image_serial.is_some())
+        {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "Ubuntu image options were set but the os is {:?}",
+                self.os
+            )));
+        }
+        if let Some(serial) = &self.ubuntu_image_serial {
+            if serial.is_empty() || !serial.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "The Ubuntu image serial {serial:?} must consist of digits and dots, e.g. 20230516"
+                )));
+            }
+        }
+        for (key, _) in &self.tags {
+            if key.starts_with(TAG_PREFIX) {
+                return Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "The tag key {key:?} is reserved for use by aws-throwaway"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn resolved_cpu_arch(&self) -> Result<CpuArch, Ec2InstanceError> {
+        self.cpu_arch
+            .or_else(|| get_arch_of_instance_type(&self.instance_type))
+            .ok_or_else(|| {
+                Ec2InstanceError::InvalidDefinition(format!(
+                    "Cannot determine the CPU architecture of instance type {}, set it via Ec2InstanceDefinition::cpu_arch",
+                    self.instance_type.as_str()
+                ))
+            })
+    }
+
+    /// The image to launch, as an SSM parameter that AWS resolves to an AMI id
+    pub(crate) fn image_id(&self, arch: CpuArch) -> String {
+        match self.os {
+            InstanceOs::Ubuntu22_04 => format!(
+                "resolve:ssm:/aws/service/canonical/ubuntu/{}/22.04/{}/{}/{}/hvm/ebs-gp2/ami-id",
+                self.ubuntu_image_variant.ssm_product(),
+                self.ubuntu_image_stream.ssm_stream(),
+                self.ubuntu_image_serial.as_deref().unwrap_or("current"),
+                arch.get_ubuntu_arch_identifier()
+            ),
+            InstanceOs::AmazonLinux2023 => format!(
+                "resolve:ssm:/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-{}",
+                arch.get_amazon_linux_arch_identifier()
+            ),
+        }
+    }
+
+    pub(crate) fn resolved_ssh_user(&self) -> &str {
+        self.ssh_user
+            .as_deref()
+            .unwrap_or_else(|| self.os.ssh_user())
+    }
+
+    pub(crate) fn resolved_root_device_name(&self) -> &str {
+        self.root_device_name
+            .as_deref()
+            .unwrap_or_else(|| self.os.root_device_name())
+    }
+
+    /// All volumes attached to the instance, starting with the root volume
+    pub(crate) fn block_devices(&self) -> Vec<BlockDevice> {
+        let mut devices = vec![BlockDevice {
+            device_name: self.resolved_root_device_name().to_owned(),
+            size_gb: self.volume_size_gb,
+            volume_type: self.volume_type.clone(),
+        }];
+        devices.extend(self.additional_volumes.iter().cloned());
+        devices
+    }
+
+    /// Whether and with which KMS key to encrypt the root volume
+    pub(crate) fn root_volume_encryption(&self) -> (Option<bool>, Option<String>) {
+        match &self.volume_encryption {
+            Some(kms_key_id) => (Some(true), kms_key_id.clone()),
+            None if self.hibernation => (Some(true), None),
+            None => (None, None),
+        }
+    }
+
+    /// The IOPS to provision for the root volume, if any
+    pub(crate) fn root_volume_iops(&self) -> Option<i32> {
+        match (self.volume_iops_per_gb, iops_limits(&self.volume_type)) {
+            (Some(ratio), Some(limits)) => Some(
+                self.volume_size_gb
+                    .saturating_mul(ratio)
+                    .clamp(limits.min, limits.max) as i32,
+            ),
+            _ => self.volume_iops,
+        }
+    }
+}
+
+/// Configured via [`Ec2InstanceDefinition::existing_key_pair`]
+#[derive(Clone, PartialEq)]
+pub(crate) struct ExistingKeyPair {
+    pub(crate) key_name: String,
+    pub(crate) private_key: String,
+}
+
+// Implemented manually so that the private key does not end up in logs.
+impl std::fmt::Debug for ExistingKeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExistingKeyPair")
+            .field("key_name", &self.key_name)
+            .field("private_key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// An EBS volume attached to an instance
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockDevice {
+    /// The device name the volume was attached at, e.g. `/dev/sdf`
+    pub device_name: String,
+    pub size_gb: u32,
+    pub volume_type: VolumeType,
+}
+
+/// Each `.` separated label must be 1 to 63 ASCII letters, digits or hyphens and may not start or end with a hyphen.
+pub(crate) fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+struct IopsLimits {
+    min: u32,
+    max: u32,
+    max_per_gb: u32,
+}
+
+/// The provisioned IOPS limits of each volume type, or None if the volume type does not support provisioned IOPS
+fn iops_limits(volume_type: &VolumeType) -> Option<IopsLimits> {
+    match volume_type {
+        VolumeType::Gp3 => Some(IopsLimits {
+            min: 3000,
+            max: 16000,
+            max_per_gb: 500,
+        }),
+        VolumeType::Io1 => Some(IopsLimits {
+            min: 100,
+            max: 64000,
+            max_per_gb: 50,
+        }),
+        VolumeType::Io2 => Some(IopsLimits {
+            min: 100,
+            max: 64000,
+            max_per_gb: 500,
+        }),
+        _ => None,
+    }
+}
+
+/// The operating system an instance is launched with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceOs {
+    Ubuntu22_04,
+    AmazonLinux2023,
+}
+
+impl InstanceOs {
+    /// The user that the AMI allows ssh logins for
+    pub(crate) fn ssh_user(&self) -> &'static str {
+        match self {
+            InstanceOs::Ubuntu22_04 => "ubuntu",
+            InstanceOs::AmazonLinux2023 => "ec2-user",
+        }
+    }
+
+    /// The device name of the root volume in the AMI
+    pub(crate) fn root_device_name(&self) -> &'static str {
+        match self {
+            InstanceOs::Ubuntu22_04 => "/dev/sda1",
+            InstanceOs::AmazonLinux2023 => "/dev/xvda",
+        }
+    }
+
+    /// The name of the systemd service running sshd
+    pub(crate) fn ssh_service(&self) -> &'static str {
+        match self {
+            InstanceOs::Ubuntu22_04 => "ssh",
+            InstanceOs::AmazonLinux2023 => "sshd",
+        }
+    }
+}
+
+/// The variant of the Ubuntu image an instance is launched with, see [`Ec2InstanceDefinition::ubuntu_image_variant`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UbuntuImageVariant {
+    /// The standard Ubuntu server image
+    Server,
+    /// A smaller image without packages intended for interactive use, which boots faster
+    Minimal,
+    /// Ubuntu Pro, which includes extended security maintenance and is billed at a higher rate
+    Pro,
+}
+
+impl UbuntuImageVariant {
+    fn ssm_product(&self) -> &'static str {
+        match self {
+            UbuntuImageVariant::Server => "server",
+            UbuntuImageVariant::Minimal => "server-minimal",
+            UbuntuImageVariant::Pro => "pro-server",
+        }
+    }
+}
+
+/// The release stream the Ubuntu image is taken from, see [`Ec2InstanceDefinition::ubuntu_image_stream`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UbuntuImageStream {
+    /// Images that have passed Canonical's release testing
+    Stable,
+    /// Images built daily, which include the latest package updates but are less tested
+    Daily,
+}
+
+impl UbuntuImageStream {
+    fn ssm_stream(&self) -> &'static str {
+        match self {
+            UbuntuImageStream::Stable => "stable",
+            UbuntuImageStream::Daily => "daily",
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use aws_sdk_ec2::error::ProvideErrorMetadata;
+use aws_sdk_ec2::types::PlacementStrategy;
+use std::fmt::Display;
+
+/// Returned by [`Aws::create_ec2_instance`](crate::Aws::create_ec2_instance) when an instance could not be created.
+#[derive(Debug)]
+pub enum Ec2InstanceError {
+    /// The [`Ec2InstanceDefinition`](crate::Ec2InstanceDefinition) was rejected before any AWS resources were created.
+    InvalidDefinition(String),
+    /// AWS does not currently have enough capacity for the requested instance type or the account has reached an instance limit.
+    /// Retrying later or with a different instance type may succeed.
+    InsufficientCapacity(String),
+    /// The credentials in use are not permitted to perform an operation required to create the instance.
+    PermissionDenied(String),
+    /// The AMI is from the AWS Marketplace and the account has not subscribed to it and accepted its terms.
+    MarketplaceSubscriptionRequired(String),
+    /// AWS rejected launching the instance type into a placement group with the configured [`PlacementStrategy`].
+    UnsupportedPlacement {
+        strategy: PlacementStrategy,
+        message: String,
+    },
+    /// The instance was launched but was not assigned its IP addresses in time,
+    /// see [`AwsBuilder::poll_timeout`](crate::AwsBuilder::poll_timeout).
+    IpAssignmentTimeout { instance_id: String },
+    /// The instance was launched but an ssh connection could not be established within the configured timeout,
+    /// see [`AwsBuilder::ssh_connect_timeout`](crate::AwsBuilder::ssh_connect_timeout).
+    SshConnectTimeout {
+        instance_id: String,
+        source: anyhow::Error,
+    },
+    /// The operation did not complete within the deadline configured by [`AwsBuilder::operation_deadline`](crate::AwsBuilder::operation_deadline).
+    /// `phase` describes what the operation was doing when the deadline was reached.
+    DeadlineExceeded { phase: String },
+    /// Any other error returned while creating the instance.
+    Other(anyhow::Error),
+}
+
+impl Ec2InstanceError {
+    pub(crate) fn from_service_error<E>(err: E) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        let message = err.message().unwrap_or_default().to_owned();
+        match err.code() {
+            Some(
+                "InsufficientInstanceCapacity"
+                | "InsufficientCapacity"
+                | "InsufficientHostCapacity"
+                | "ReservationCapacityExceeded"
+                | "InstanceLimitExceeded"
+                | "VcpuLimitExceeded",
+            ) => Ec2InstanceError::InsufficientCapacity(message),
+            Some("UnauthorizedOperation" | "AuthFailure" | "Blocked") => {
+                Ec2InstanceError::PermissionDenied(message)
+            }
+            Some("OptInRequired") => Ec2InstanceError::MarketplaceSubscriptionRequired(message),
+            _ => Ec2InstanceError::Other(anyhow::anyhow!(err)),
+        }
+    }
+}
+
+impl Display for Ec2InstanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ec2InstanceError::InvalidDefinition(message) => {
+                write!(f, "Invalid instance definition: {message}")
+            }
+            Ec2InstanceError::InsufficientCapacity(message) => {
+                write!(f, "Insufficient capacity to create instance: {message}")
+            }
+            Ec2InstanceError::PermissionDenied(message) => {
+                write!(f, "Permission denied while creating instance: {message}")
+            }
+            Ec2InstanceError::MarketplaceSubscriptionRequired(message) => write!(
+                f,
+                "The AMI requires subscribing to it and accepting its terms at https://aws.amazon.com/marketplace before it can be launched: {message}"
+            ),
+            Ec2InstanceError::UnsupportedPlacement { strategy, message } => write!(
+                f,
+                "The instance cannot be launched into a {} placement group: {message}",
+                strategy.as_str()
+            ),
+            Ec2InstanceError::IpAssignmentTimeout { instance_id } => write!(
+                f,
+                "Timed out waiting for instance {instance_id} to be assigned an IP address"
+            ),
+            Ec2InstanceError::SshConnectTimeout {
+                instance_id,
+                source,
+            } => write!(
+                f,
+                "Failed to connect to instance {instance_id} over ssh: {source:?}"
+            ),
+            Ec2InstanceError::DeadlineExceeded { phase } => {
+                write!(f, "Exceeded the operation deadline while {phase}")
+            }
+            Ec2InstanceError::Other(err) => write!(f, "Failed to create instance: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Ec2InstanceError {}
+
+/// Returned by [`AwsBuilder::build`](crate::AwsBuilder::build) when the resources shared by all instances could not be created.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The [`AwsBuilder`](crate::AwsBuilder) was configured with options that cannot be combined.
+    InvalidConfiguration(String),
+    /// The region has no default VPC, which aws-throwaway launches all resources into.
+    /// Create one with `aws ec2 create-default-vpc` or via the VPC console under Actions > Create default VPC.
+    NoDefaultVpc,
+    /// The credentials are missing, invalid or expired.
+    InvalidCredentials(String),
+    /// The credentials in use are not permitted to perform an operation required by aws-throwaway.
+    PermissionDenied(String),
+    /// The region is not enabled for the account.
+    RegionNotEnabled(String),
+    /// The placement group passed to [`AwsBuilder::use_placement_group_name`](crate::AwsBuilder::use_placement_group_name) does not exist.
+    PlacementGroupNotFound(String),
+    /// The build did not complete within the deadline configured by [`AwsBuilder::operation_deadline`](crate::AwsBuilder::operation_deadline).
+    /// `phase` describes what the build was doing when the deadline was reached.
+    /// Any resources already created will be removed by the next cleanup.
+    DeadlineExceeded { phase: String },
+    /// Any other error returned by AWS.
+    Other(anyhow::Error),
+}
+
+impl BuildError {
+    pub(crate) fn from_service_error<E>(err: E) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        let message = err.message().unwrap_or_default().to_owned();
+        match err.code() {
+            Some(
+                "AuthFailure" | "InvalidClientTokenId" | "ExpiredToken" | "SignatureDoesNotMatch",
+            ) => BuildError::InvalidCredentials(message),
+            Some("UnauthorizedOperation" | "AccessDenied" | "Blocked") => {
+                BuildError::PermissionDenied(message)
+            }
+            Some("OptInRequired") => BuildError::RegionNotEnabled(message),
+            _ => BuildError::Other(anyhow::anyhow!(err)),
+        }
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::InvalidConfiguration(message) => {
+                write!(f, "Invalid configuration: {message}")
+            }
+            BuildError::NoDefaultVpc => write!(
+                f,
+                "The region has no default VPC, which aws-throwaway launches all resources into. \
+                Create one with `aws ec2 create-default-vpc` or via the VPC console under Actions > Create default VPC"
+            ),
+            BuildError::InvalidCredentials(message) => write!(
+                f,
+                "The AWS credentials are invalid or expired, refer to https://docs.aws.amazon.com/sdk-for-rust/latest/dg/credentials.html: {message}"
+            ),
+            BuildError::PermissionDenied(message) => write!(
+                f,
+                "The AWS credentials are not permitted to perform an operation required by aws-throwaway, grant the IAM user access to it: {message}"
+            ),
+            BuildError::RegionNotEnabled(message) => write!(
+                f,
+                "The region is not enabled for the account, enable it in the account settings of the AWS console: {message}"
+            ),
+            BuildError::PlacementGroupNotFound(name) => {
+                write!(f, "The placement group {name:?} does not exist")
+            }
+            BuildError::DeadlineExceeded { phase } => write!(
+                f,
+                "Exceeded the operation deadline while {phase}, any resources already created will be removed by the next cleanup"
+            ),
+            BuildError::Other(err) => write!(f, "Failed to create AWS resources: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
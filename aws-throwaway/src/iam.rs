@@ -1,16 +1,162 @@
+use crate::BuildError;
 use aws_config::SdkConfig;
 
-pub async fn user_name(config: &SdkConfig) -> String {
+pub async fn user_name(config: &SdkConfig) -> Result<String, BuildError> {
     let client = aws_sdk_iam::Client::new(config);
-    client
+    Ok(client
         .get_user()
         .send()
         .await
-        .map_err(|e| e.into_service_error())
-        .unwrap()
+        .map_err(|e| BuildError::from_service_error(e.into_service_error()))?
         .user()
         .unwrap()
         .user_name()
         .unwrap()
-        .to_string()
+        .to_string())
+}
+
+/// Every AWS action performed by aws-throwaway, kept in sync with the requests made throughout the crate.
+const REQUIRED_ACTIONS: &[&str] = &[
+    "ec2:AssociateAddress",
+    "ec2:AuthorizeSecurityGroupIngress",
+    "ec2:CreateKeyPair",
+    "ec2:CreatePlacementGroup",
+    "ec2:CreateSecurityGroup",
+    "ec2:CreateTags",
+    "ec2:DeleteKeyPair",
+    "ec2:DeletePlacementGroup",
+    "ec2:DeleteSecurityGroup",
+    "ec2:DescribeAddresses",
+    "ec2:DescribeInstanceStatus",
+    "ec2:DescribeInstanceTypes",
+    "ec2:DescribeInstances",
+    "ec2:DescribePlacementGroups",
+    "ec2:DescribeTags",
+    "ec2:DescribeVolumes",
+    "ec2:DescribeVolumesModifications",
+    "ec2:DescribeVpcs",
+    "ec2:DisassociateAddress",
+    "ec2:GetConsoleOutput",
+    "ec2:ModifyVolume",
+    "ec2:RebootInstances",
+    "ec2:RunInstances",
+    "ec2:StartInstances",
+    "ec2:StopInstances",
+    "ec2:TerminateInstances",
+    "iam:GetUser",
+    // AMIs are looked up via the public SSM parameters published by Canonical and Amazon
+    "ssm:GetParameters",
+];
+
+pub fn required_policy_json() -> String {
+    let actions: Vec<String> = REQUIRED_ACTIONS
+        .iter()
+        .map(|action| format!("                \"{action}\""))
+        .collect();
+    format!(
+        r#"{{
+    "Version": "2012-10-17",
+    "Statement": [
+        {{
+            "Effect": "Allow",
+            "Action": [
+{}
+            ],
+            "Resource": "*"
+        }}
+    ]
+}}"#,
+        actions.join(",\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The source of every module that makes requests with an aws-sdk client, alongside the IAM service prefix of that client
+    const SOURCES: &[(&str, &str)] = &[
+        ("ec2", include_str!("lib.rs")),
+        ("ec2", include_str!("ec2_instance.rs")),
+        ("ec2", include_str!("cluster.rs")),
+        ("ec2", include_str!("pool.rs")),
+        ("ec2", include_str!("blocking.rs")),
+        ("iam", include_str!("iam.rs")),
+    ];
+
+    /// Finds every call to an operation on `client` in `source`, returned as IAM actions e.g. `ec2:DescribeInstances`.
+    /// Test modules are skipped so that the test fixtures below are not picked up.
+    fn called_actions(service: &str, source: &str) -> Vec<String> {
+        let source = source.split("#[cfg(test)]").next().unwrap();
+        let mut actions = vec![];
+        for (index, _) in source.match_indices("client") {
+            let preceded_by_ident = source[..index]
+                .chars()
+                .next_back()
+                .map_or(false, |c| c.is_ascii_alphanumeric() || c == '_');
+            let Some(rest) = source[index + "client".len()..].trim_start().strip_prefix('.')
+            else {
+                continue;
+            };
+            let operation: String = rest
+                .trim_start()
+                .chars()
+                .take_while(|c| c.is_ascii_lowercase() || *c == '_')
+                .collect();
+            let is_call = rest.trim_start()[operation.len()..].starts_with('(');
+            if preceded_by_ident || !is_call || operation.is_empty() || operation == "clone" {
+                continue;
+            }
+            let pascal_case: String = operation
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                        .unwrap_or_default()
+                })
+                .collect();
+            actions.push(format!("{service}:{pascal_case}"));
+        }
+        actions
+    }
+
+    #[test]
+    fn required_actions_match_requests() {
+        let called: Vec<String> = SOURCES
+            .iter()
+            .flat_map(|(service, source)| called_actions(service, source))
+            .collect();
+
+        let missing: Vec<&String> = called
+            .iter()
+            .filter(|action| !REQUIRED_ACTIONS.contains(&action.as_str()))
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "Requests are made that are missing from REQUIRED_ACTIONS: {missing:?}"
+        );
+
+        // ssm:GetParameters is performed by EC2 on our behalf when resolving an AMI so it is never called directly.
+        let unused: Vec<&&str> = REQUIRED_ACTIONS
+            .iter()
+            .filter(|action| {
+                **action != "ssm:GetParameters" && !called.iter().any(|x| x == *action)
+            })
+            .collect();
+        assert!(
+            unused.is_empty(),
+            "REQUIRED_ACTIONS contains actions that are never requested: {unused:?}"
+        );
+    }
+
+    #[test]
+    fn called_actions_parsing() {
+        let source = "self.client\n    .describe_instances()\nlet client = client.clone();\nhyper_client.get(uri)";
+        assert_eq!(
+            called_actions("ec2", source),
+            vec!["ec2:DescribeInstances".to_owned()]
+        );
+    }
 }
@@ -1,25 +1,88 @@
+/// Logs an AWS request at debug level when audit logging is enabled, see [`AwsBuilder::audit_log`].
+/// Secrets such as key material and user-data must never be passed as fields.
+macro_rules! audit {
+    ($enabled:expr, $operation:literal $(, $($fields:tt)*)?) => {
+        if $enabled {
+            tracing::debug!(target: "aws_throwaway::audit", operation = $operation $(, $($fields)*)?);
+        }
+    };
+}
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod caller_ip;
+pub mod cluster;
+mod cpu_arch;
 pub mod ec2_instance;
+pub mod ec2_instance_definition;
+mod error;
 mod iam;
+pub mod pool;
+mod proxy;
+mod sftp;
 mod ssh;
-pub use aws_sdk_ec2::types::InstanceType;
+pub use aws_sdk_ec2::types::{
+    HostnameType, HttpTokensState, InstanceStateName, InstanceType, PlacementStrategy,
+    ResourceType, Tenancy, VolumeType,
+};
+pub use cpu_arch::CpuArch;
+pub use ec2_instance_definition::{
+    BlockDevice, Ec2InstanceDefinition, InstanceOs, UbuntuImageStream, UbuntuImageVariant,
+};
+pub use error::{BuildError, Ec2InstanceError};
+pub use ssh::{CommandOutput, JumpHost, PortForward, ShellEvent};
 
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::SdkConfig;
+use aws_sdk_ec2::error::ProvideErrorMetadata;
 use aws_sdk_ec2::types::{
-    BlockDeviceMapping, EbsBlockDevice, KeyType, ResourceType, Tag, TagSpecification, VolumeType,
+    ArchitectureType, BlockDeviceMapping, CapacityReservationSpecification,
+    CapacityReservationTarget, EbsBlockDevice, EbsOptimizedSupport, HibernationOptionsRequest,
+    IamInstanceProfileSpecification, InstanceMetadataOptionsRequest, KeyType, Placement,
+    PrivateDnsNameOptionsRequest, RunInstancesMonitoringEnabled, ShutdownBehavior, Tag,
+    TagSpecification,
 };
 use aws_sdk_ec2::{config::Region, types::Filter};
 use base64::Engine;
-use ec2_instance::Ec2Instance;
+use ec2_instance::{
+    CreationTimings, Ec2Instance, InstanceKeys, InstancePlacement, LaunchedInstance,
+    NetworkInterface,
+};
+use futures::future::try_join_all;
+use ssh::SshCredentials;
 use ssh_key::rand_core::OsRng;
 use ssh_key::PrivateKey;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
 use uuid::Uuid;
 
+/// Loads the aws-sdk config, sending requests through the proxy in the `HTTPS_PROXY` environment variable if set.
+///
+/// Panics if `HTTPS_PROXY` is not a valid URL.
 pub async fn config() -> SdkConfig {
+    config_with_proxy(proxy::https_proxy_from_env().as_deref())
+        .await
+        .unwrap_or_else(|e| panic!("{e:?}"))
+}
+
+async fn config_with_proxy(https_proxy: Option<&str>) -> anyhow::Result<SdkConfig> {
     let region_provider = RegionProviderChain::first_try(Region::new("us-east-1"));
-    aws_config::from_env().region(region_provider).load().await
+    let mut loader = aws_config::from_env().region(region_provider);
+    if let Some(https_proxy) = https_proxy {
+        tracing::info!("Sending AWS requests through proxy {https_proxy}");
+        loader = loader.http_connector(proxy::connector(https_proxy)?);
+    }
+    Ok(loader.load().await)
 }
 
+/// Creates and cleans up AWS resources, constructed via [`Aws::new`] or [`Aws::builder`].
+///
+/// All async methods must be called from within a tokio runtime, aws-throwaway never creates a runtime of its own outside of the `blocking` feature.
 pub struct Aws {
     client: aws_sdk_ec2::Client,
     user_name: String,
@@ -29,40 +92,413 @@ pub struct Aws {
     host_public_key_bytes: Vec<u8>,
     host_private_key: String,
     security_group: String,
+    placement_group: Option<String>,
+    placement_strategy: Option<PlacementStrategy>,
+    audit_log: bool,
+    ssh_connect_timeout: Duration,
+    operation_deadline: Option<Duration>,
+    use_public_addresses: bool,
+    poll_policy: PollPolicy,
+    availability_zones: Vec<String>,
+    ssh_client_alive: (Duration, u32),
+    replace_host_key: bool,
+    run_token: String,
+    scope_cleanup_to_run: bool,
+    cleanup_waits_for_termination: bool,
+    jump_host: Option<Arc<JumpHost>>,
+    instance_type_info: Mutex<HashMap<InstanceType, InstanceTypeInfo>>,
+    /// The index into `availability_zones` that the next instance will be launched into, modulo its length
+    next_availability_zone: AtomicUsize,
 }
 
 // include a magic number in the keyname to avoid collisions
 // This can never change or we may fail to cleanup resources.
+const TAG_PREFIX: &str = "aws-throwaway-23c2d22c-d929-43fc-b2a4-c1c72f0b733f";
 const USER_TAG_NAME: &str = "aws-throwaway-23c2d22c-d929-43fc-b2a4-c1c72f0b733f:user";
+/// Identifies the [`Aws`] that created a resource, used by [`AwsBuilder::scope_cleanup_to_run`]
+const RUN_TAG_NAME: &str = "aws-throwaway-23c2d22c-d929-43fc-b2a4-c1c72f0b733f:run";
+
+/// AWS limits user data to 16KB before base64 encoding.
+const MAX_USER_DATA_BYTES: usize = 16 * 1024;
+
+/// The addresses AWS assigned to a newly launched instance
+struct AssignedAddresses {
+    public_ip: Option<IpAddr>,
+    private_ip: IpAddr,
+    private_dns_name: String,
+    placement: InstancePlacement,
+    network_interfaces: Vec<NetworkInterface>,
+    /// How long after polling began the addresses were found to be assigned
+    assigned_after: Duration,
+}
+
+/// Controls how often and for how long AWS is polled while waiting on a newly launched instance.
+#[derive(Clone, Copy)]
+struct PollPolicy {
+    initial_interval: Duration,
+    max_interval: Duration,
+    timeout: Duration,
+}
+
+impl PollPolicy {
+    /// The interval to wait after waiting `interval`, doubling until `max_interval` is reached.
+    fn next_interval(&self, interval: Duration) -> Duration {
+        (interval * 2).min(self.max_interval)
+    }
+}
+
+/// Configures an [`Aws`] before it is created, obtained via [`Aws::builder`]
+pub struct AwsBuilder {
+    https_proxy: Option<String>,
+    placement_strategy: Option<PlacementStrategy>,
+    placement_group_name: Option<String>,
+    audit_log: bool,
+    ssh_connect_timeout: Duration,
+    operation_deadline: Option<Duration>,
+    use_public_addresses: bool,
+    poll_policy: PollPolicy,
+    availability_zones: Vec<String>,
+    ssh_client_alive: (Duration, u32),
+    replace_host_key: bool,
+    scope_cleanup_to_run: bool,
+    cleanup_waits_for_termination: bool,
+    jump_host: Option<JumpHost>,
+    open_ports: Vec<u16>,
+    ssh_ingress: SshIngress,
+    key_pair: Option<(String, String)>,
+}
+
+enum SshIngress {
+    Cidr(String),
+    CallerPublicIp,
+}
+
+impl AwsBuilder {
+    /// Send all AWS API requests through the specified proxy, e.g. `http://proxy.example.com:3128`.
+    /// Defaults to the value of the `HTTPS_PROXY` environment variable, if set.
+    ///
+    /// SSH connections to instances are made directly and do not go through the proxy.
+    /// If `url` is not a valid URL [`AwsBuilder::build`] returns [`BuildError::InvalidConfiguration`].
+    pub fn https_proxy(mut self, url: impl Into<String>) -> Self {
+        self.https_proxy = Some(url.into());
+        self
+    }
+
+    /// Launch all instances into a placement group created with the specified strategy.
+    /// By default no placement group is used.
+    ///
+    /// Note that [`PlacementStrategy::Cluster`] only supports some instance types,
+    /// if AWS rejects the instance type [`Aws::create_ec2_instance`] will return [`Ec2InstanceError::UnsupportedPlacement`].
+    pub fn placement_strategy(mut self, strategy: PlacementStrategy) -> Self {
+        self.placement_strategy = Some(strategy);
+        self
+    }
+
+    /// Launch all instances into an existing placement group instead of creating a new one,
+    /// e.g. to get consistent latency between instances created by separate [`Aws`]s in different processes.
+    /// The placement group's strategy is used as if set via [`AwsBuilder::placement_strategy`], so that must not also be set.
+    ///
+    /// The placement group is never deleted by cleanup, since it was not created by aws-throwaway.
+    /// Passing `None` restores the default of creating a placement group only when a strategy is set.
+    pub fn use_placement_group_name(mut self, name: Option<String>) -> Self {
+        self.placement_group_name = name;
+        self
+    }
+
+    /// When enabled every request made to AWS is logged at debug level with the target `aws_throwaway::audit`,
+    /// recording the operation name and its key parameters such as instance type, tags and placement.
+    /// Key material and user-data are never logged.
+    /// Disabled by default.
+    pub fn audit_log(mut self, enabled: bool) -> Self {
+        self.audit_log = enabled;
+        self
+    }
+
+    /// How long to keep retrying to establish an ssh connection with a newly launched, started or rebooted instance.
+    /// If it is exceeded [`Aws::create_ec2_instance`] returns [`Ec2InstanceError::SshConnectTimeout`].
+    /// Defaults to 5 minutes.
+    pub fn ssh_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.ssh_connect_timeout = timeout;
+        self
+    }
+
+    /// When true aws-throwaway connects to instances via their public IP, otherwise their private IP is used,
+    /// which requires the machine running aws-throwaway to have access to the VPC, e.g. when running on another instance in the VPC.
+    /// Can be overridden per instance via [`Ec2InstanceDefinition::connect_via_public_ip`].
+    /// Defaults to true.
+    pub fn use_public_addresses(mut self, use_public_addresses: bool) -> Self {
+        self.use_public_addresses = use_public_addresses;
+        self
+    }
+
+    /// While waiting for a newly launched instance to be assigned its IP addresses or to accept its elastic IP,
+    /// AWS is first polled after `initial_interval`, with the interval doubling after each attempt up to `max_interval`.
+    /// Defaults to an initial interval of 1 second and a max interval of 10 seconds.
+    pub fn poll_interval(mut self, initial_interval: Duration, max_interval: Duration) -> Self {
+        self.poll_policy.initial_interval = initial_interval;
+        self.poll_policy.max_interval = max_interval.max(initial_interval);
+        self
+    }
+
+    /// How long to keep polling for a newly launched instance to be assigned its IP addresses and to accept its elastic IP.
+    /// If it is exceeded [`Aws::create_ec2_instance`] returns [`Ec2InstanceError::IpAssignmentTimeout`].
+    ///
+    /// Also bounds how long [`Ec2Instance::stop`], [`Ec2Instance::hibernate`], [`Ec2Instance::start`], [`Ec2Instance::refresh_connection`]
+    /// and [`Ec2Instance::terminate`] wait for the instance to change state, unless [`AwsBuilder::operation_deadline`] is set in which case that is used instead.
+    /// Defaults to 3 minutes.
+    pub fn poll_timeout(mut self, timeout: Duration) -> Self {
+        self.poll_policy.timeout = timeout;
+        self
+    }
+
+    /// Spread instances round-robin across the specified availability zones, e.g. `us-east-1a`, so that a capacity shortage in one zone does not block every instance.
+    /// By default AWS picks the availability zone of each instance.
+    ///
+    /// Cluster placement groups are confined to a single availability zone,
+    /// so [`AwsBuilder::build`] returns [`BuildError::InvalidConfiguration`] if this is combined with [`PlacementStrategy::Cluster`].
+    pub fn availability_zones(mut self, availability_zones: Vec<String>) -> Self {
+        self.availability_zones = availability_zones;
+        self
+    }
+
+    /// Configures the keepalives the ssh server on each instance sends to idle clients, including aws-throwaway itself and sessions opened via [`Ec2Instance::ssh_instructions`].
+    /// A keepalive is sent after `interval` of inactivity and the client is disconnected after `count_max` keepalives go unanswered.
+    /// Defaults to an interval of 30 seconds and a count max of 3.
+    pub fn ssh_client_alive(mut self, interval: Duration, count_max: u32) -> Self {
+        self.ssh_client_alive = (interval, count_max);
+        self
+    }
+
+    /// By default aws-throwaway replaces the ed25519 host key of each instance via its user data, so that the instance can be trusted from the first connection.
+    /// When false the host key is left untouched, which is needed for hardened AMIs that forbid replacing it or have one baked in.
+    /// Instead the instance's own ed25519 host key is read from the host keys cloud-init prints to the console output,
+    /// which AWS may take a few minutes to make available, slowing down instance creation.
+    pub fn replace_host_key(mut self, replace_host_key: bool) -> Self {
+        self.replace_host_key = replace_host_key;
+        self
+    }
+
+    /// When true, cleanup only removes the resources created by this [`Aws`], identified by a unique token tagged onto every resource it creates.
+    /// This allows multiple processes using the same IAM user, e.g. concurrent CI jobs, to safely share an account.
+    ///
+    /// As a consequence [`AwsBuilder::build`] no longer cleans up resources left behind by previous runs,
+    /// call [`Aws::cleanup_resources_static`] when no other runs are active to remove them.
+    /// Defaults to false, cleaning up every resource created by the IAM user.
+    pub fn scope_cleanup_to_run(mut self, scope_cleanup_to_run: bool) -> Self {
+        self.scope_cleanup_to_run = scope_cleanup_to_run;
+        self
+    }
+
+    /// When true, cleanup waits for terminated instances to fully shut down before deleting security groups,
+    /// so that the security groups are deleted in the same cleanup instead of being left for a future cleanup.
+    /// This makes cleanup slower but prevents leaked security groups accumulating towards the per-VPC limit.
+    /// Defaults to false.
+    pub fn cleanup_waits_for_termination(mut self, wait: bool) -> Self {
+        self.cleanup_waits_for_termination = wait;
+        self
+    }
+
+    /// Tunnel every ssh connection to an instance through `jump_host`, like ssh's `ProxyJump`,
+    /// for networks where instances can only be reached via a bastion host.
+    /// This is usually combined with `use_public_addresses(false)` so that instances are reached via their private IP from the jump host.
+    ///
+    /// The jump host must be able to reach port 22 of the instances, e.g. by allowing it via [`AwsBuilder::ssh_cidr`].
+    /// By default instances are connected to directly.
+    pub fn ssh_jump_host(mut self, jump_host: JumpHost) -> Self {
+        self.jump_host = Some(jump_host);
+        self
+    }
+
+    /// Only allow ssh connections to instances from addresses within `cidr`, e.g. `203.0.113.0/24`.
+    /// Defaults to `0.0.0.0/0`, allowing connections from anywhere.
+    pub fn ssh_cidr(mut self, cidr: impl Into<String>) -> Self {
+        self.ssh_ingress = SshIngress::Cidr(cidr.into());
+        self
+    }
+
+    /// Only allow ssh connections to instances from the public IP of the machine running aws-throwaway.
+    /// The public IP is detected during [`AwsBuilder::build`] via <https://checkip.amazonaws.com>, if that fails [`BuildError::Other`] is returned.
+    pub fn ssh_from_caller_public_ip(mut self) -> Self {
+        self.ssh_ingress = SshIngress::CallerPublicIp;
+        self
+    }
+
+    /// Launch instances with an existing EC2 key pair instead of creating a new key pair.
+    /// `private_key` must be the private key of the key pair in OpenSSH or PEM format, it is used by aws-throwaway to ssh into instances.
+    ///
+    /// The key pair is not managed by aws-throwaway and so will never be deleted during cleanup.
+    /// To use an existing key pair for only some instances use [`Ec2InstanceDefinition::existing_key_pair`] instead.
+    pub fn use_key_pair(
+        mut self,
+        key_name: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Self {
+        self.key_pair = Some((key_name.into(), private_key.into()));
+        self
+    }
+
+    /// Allow inbound TCP connections from anywhere to `port` on all instances, can be called multiple times to open multiple ports.
+    /// By default only port 22 is open for ssh, instances can always reach each other on any port.
+    ///
+    /// Opening a port more than once has no further effect.
+    /// Port 22 is ignored since it is always open, use [`AwsBuilder::ssh_cidr`] to control who can reach it.
+    pub fn open_port(mut self, port: u16) -> Self {
+        // AWS rejects duplicate ingress rules
+        if port != 22 && !self.open_ports.contains(&port) {
+            self.open_ports.push(port);
+        }
+        self
+    }
+
+    /// Caps how long [`AwsBuilder::build`], [`Aws::create_ec2_instance`], [`Aws::create_ec2_instances`] and [`Aws::cleanup_resources`] may each take.
+    /// This is intended for CI jobs with a hard time budget, so that a hung operation fails with a descriptive error instead of consuming the entire budget.
+    ///
+    /// When exceeded:
+    /// * `build` returns [`BuildError::DeadlineExceeded`], naming the phase that ran long.
+    /// * instance creation returns [`Ec2InstanceError::DeadlineExceeded`] and terminates any instances it had launched.
+    /// * cleanup logs an error, any remaining resources are removed by the next cleanup.
+    /// * waiting for an existing instance to change state, e.g. in [`Ec2Instance::stop`], returns an error.
+    ///
+    /// By default there is no deadline.
+    pub fn operation_deadline(mut self, deadline: Duration) -> Self {
+        self.operation_deadline = Some(deadline);
+        self
+    }
+
+    /// Creates the [`Aws`], cleaning up resources left behind by previous runs and creating the resources shared by all instances.
+    pub async fn build(self) -> Result<Aws, BuildError> {
+        if !self.availability_zones.is_empty()
+            && self.placement_strategy == Some(PlacementStrategy::Cluster)
+        {
+            return Err(BuildError::InvalidConfiguration("AwsBuilder::availability_zones cannot be used with PlacementStrategy::Cluster since cluster placement groups are confined to a single availability zone".to_owned()));
+        }
+        if self.placement_group_name.is_some() && self.placement_strategy.is_some() {
+            return Err(BuildError::InvalidConfiguration("AwsBuilder::use_placement_group_name cannot be combined with AwsBuilder::placement_strategy since the existing placement group already has a strategy".to_owned()));
+        }
+        let operation_deadline = self.operation_deadline;
+        let phase = Mutex::new("loading config");
+        let build = Aws::from_builder(self, &phase);
+        match operation_deadline {
+            Some(deadline) => tokio::time::timeout(deadline, build)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(BuildError::DeadlineExceeded {
+                        phase: phase.lock().unwrap().to_string(),
+                    })
+                }),
+            None => build.await,
+        }
+    }
+}
 
 impl Aws {
-    pub async fn new() -> Self {
-        let config = config().await;
-        let user_name = iam::user_name(&config).await;
-        let keyname = format!("aws-throwaway-{user_name}-{}", Uuid::new_v4());
-        let client = aws_sdk_ec2::Client::new(&config);
+    /// Equivalent to `Aws::builder().build().await`
+    pub async fn new() -> Result<Self, BuildError> {
+        Aws::builder().build().await
+    }
 
-        // Cleanup any resources that were previously failed to cleanup
-        Self::cleanup_resources_inner(&client, &user_name).await;
+    pub fn builder() -> AwsBuilder {
+        AwsBuilder {
+            https_proxy: proxy::https_proxy_from_env(),
+            placement_strategy: None,
+            placement_group_name: None,
+            audit_log: false,
+            ssh_connect_timeout: Duration::from_secs(5 * 60),
+            operation_deadline: None,
+            use_public_addresses: true,
+            poll_policy: PollPolicy {
+                initial_interval: Duration::from_secs(1),
+                max_interval: Duration::from_secs(10),
+                timeout: Duration::from_secs(3 * 60),
+            },
+            availability_zones: vec![],
+            ssh_client_alive: (Duration::from_secs(30), 3),
+            replace_host_key: true,
+            scope_cleanup_to_run: false,
+            cleanup_waits_for_termination: false,
+            jump_host: None,
+            open_ports: vec![],
+            ssh_ingress: SshIngress::Cidr("0.0.0.0/0".to_owned()),
+            key_pair: None,
+        }
+    }
 
-        let keypair = client
-            .create_key_pair()
-            .key_name(&keyname)
-            .key_type(KeyType::Ed25519)
-            .tag_specifications(
-                TagSpecification::builder()
-                    .resource_type(ResourceType::KeyPair)
-                    .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
-                    .build(),
+    /// `phase` is updated as resources are created so that [`AwsBuilder::build`] can report where it exceeded its deadline.
+    async fn from_builder(
+        builder: AwsBuilder,
+        phase: &Mutex<&'static str>,
+    ) -> Result<Self, BuildError> {
+        let config = config_with_proxy(builder.https_proxy.as_deref())
+            .await
+            .map_err(|e| BuildError::InvalidConfiguration(format!("{e:#}")))?;
+        let user_name = iam::user_name(&config).await?;
+        let client = aws_sdk_ec2::Client::new(&config);
+        let audit_log = builder.audit_log;
+        let run_token = Uuid::new_v4().to_string();
+
+        // Cleanup any resources that were previously failed to cleanup.
+        // When cleanup is scoped to the run, resources from other runs may belong to concurrently running processes so must be left alone.
+        if !builder.scope_cleanup_to_run {
+            *phase.lock().unwrap() = "cleaning up resources from previous runs";
+            Self::cleanup_resources_confirmed(
+                &client,
+                Some(&user_name),
+                None,
+                audit_log,
+                builder.cleanup_waits_for_termination,
+                &|_, _| true,
             )
+            .await;
+        }
+
+        // Security groups and instances are created in the default VPC,
+        // without one AWS fails with errors that do not explain the cause.
+        *phase.lock().unwrap() = "checking for a default VPC";
+        audit!(audit_log, "DescribeVpcs", is_default = true);
+        let default_vpcs = client
+            .describe_vpcs()
+            .filters(Filter::builder().name("isDefault").values("true").build())
             .send()
             .await
-            .map_err(|e| e.into_service_error())
-            .unwrap();
-        let client_private_key = keypair.key_material().unwrap().to_string();
-        tracing::info!("client_private_key:\n{}", client_private_key);
+            .map_err(|e| BuildError::from_service_error(e.into_service_error()))?;
+        if default_vpcs.vpcs().unwrap_or_default().is_empty() {
+            return Err(BuildError::NoDefaultVpc);
+        }
+
+        let (keyname, client_private_key) = match builder.key_pair {
+            // The key pair is not tagged by us so it will never be deleted by cleanup.
+            Some(key_pair) => key_pair,
+            None => {
+                *phase.lock().unwrap() = "creating the key pair";
+                let keyname = format!("aws-throwaway-{user_name}-{}", Uuid::new_v4());
+                audit!(audit_log, "CreateKeyPair", key_name = keyname);
+                let keypair = client
+                    .create_key_pair()
+                    .key_name(&keyname)
+                    .key_type(KeyType::Ed25519)
+                    .tag_specifications(
+                        TagSpecification::builder()
+                            .resource_type(ResourceType::KeyPair)
+                            .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                            .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
+                            .build(),
+                    )
+                    .send()
+                    .await
+                    .map_err(|e| BuildError::from_service_error(e.into_service_error()))?;
+                let client_private_key = keypair.key_material().unwrap().to_string();
+                tracing::info!("client_private_key:\n{}", client_private_key);
+                (keyname, client_private_key)
+            }
+        };
 
+        *phase.lock().unwrap() = "creating the security group";
         let security_group = format!("aws-throwaway-{user_name}-{}", Uuid::new_v4());
+        audit!(
+            audit_log,
+            "CreateSecurityGroup",
+            group_name = security_group
+        );
         client
             .create_security_group()
             .group_name(&security_group)
@@ -72,13 +508,19 @@ impl Aws {
                     .resource_type(ResourceType::SecurityGroup)
                     .tags(Tag::builder().key("Name").value("aws-throwaway").build())
                     .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                    .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
                     .build(),
             )
             .send()
             .await
-            .map_err(|e| e.into_service_error())
-            .unwrap();
+            .map_err(|e| BuildError::from_service_error(e.into_service_error()))?;
         tracing::info!("created security group");
+        audit!(
+            audit_log,
+            "AuthorizeSecurityGroupIngress",
+            group_name = security_group,
+            source_security_group_name = security_group
+        );
         assert!(client
             .authorize_security_group_ingress()
             .group_name(&security_group)
@@ -93,43 +535,149 @@ impl Aws {
                             .build()
                     )
                     .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                    .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
                     .build(),
             )
             .send()
             .await
-            .map_err(|e| e.into_service_error())
-            .unwrap()
+            .map_err(|e| BuildError::from_service_error(e.into_service_error()))?
             .r#return()
             .unwrap());
         tracing::info!("created security group rule");
+        let ssh_cidr = match builder.ssh_ingress {
+            SshIngress::Cidr(cidr) => cidr,
+            SshIngress::CallerPublicIp => {
+                let ip = caller_ip::public_ip(builder.https_proxy.as_deref())
+                    .await
+                    .map_err(|e| {
+                        BuildError::Other(
+                            e.context("Failed to detect the public IP of this machine"),
+                        )
+                    })?;
+                format!("{ip}/32")
+            }
+        };
+        audit!(
+            audit_log,
+            "AuthorizeSecurityGroupIngress",
+            group_name = security_group,
+            port = 22,
+            cidr_ip = ssh_cidr
+        );
         assert!(client
             .authorize_security_group_ingress()
             .group_name(&security_group)
             .ip_protocol("tcp")
             .from_port(22)
             .to_port(22)
-            .cidr_ip("0.0.0.0/0")
+            .cidr_ip(&ssh_cidr)
             .tag_specifications(
                 TagSpecification::builder()
                     .resource_type(ResourceType::SecurityGroupRule)
                     .tags(Tag::builder().key("Name").value("ssh").build())
                     .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                    .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
                     .build(),
             )
             .send()
             .await
-            .map_err(|e| e.into_service_error())
-            .unwrap()
+            .map_err(|e| BuildError::from_service_error(e.into_service_error()))?
             .r#return()
             .unwrap());
         tracing::info!("created security group rule");
+        for port in &builder.open_ports {
+            audit!(
+                audit_log,
+                "AuthorizeSecurityGroupIngress",
+                group_name = security_group,
+                port,
+                cidr_ip = "0.0.0.0/0"
+            );
+            assert!(client
+                .authorize_security_group_ingress()
+                .group_name(&security_group)
+                .ip_protocol("tcp")
+                .from_port(*port as i32)
+                .to_port(*port as i32)
+                .cidr_ip("0.0.0.0/0")
+                .tag_specifications(
+                    TagSpecification::builder()
+                        .resource_type(ResourceType::SecurityGroupRule)
+                        .tags(
+                            Tag::builder()
+                                .key("Name")
+                                .value(format!("port {port}"))
+                                .build()
+                        )
+                        .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                        .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| BuildError::from_service_error(e.into_service_error()))?
+                .r#return()
+                .unwrap());
+            tracing::info!("created security group rule for port {port}");
+        }
+
+        let mut placement_strategy = builder.placement_strategy.clone();
+        let placement_group = if let Some(name) = &builder.placement_group_name {
+            *phase.lock().unwrap() = "looking up the placement group";
+            audit!(audit_log, "DescribePlacementGroups", group_name = name);
+            let groups = client
+                .describe_placement_groups()
+                .group_names(name)
+                .send()
+                .await
+                .map_err(|e| BuildError::from_service_error(e.into_service_error()))?;
+            let strategy = groups
+                .placement_groups()
+                .and_then(|groups| groups.first())
+                .and_then(|group| group.strategy())
+                .cloned()
+                .ok_or_else(|| BuildError::PlacementGroupNotFound(name.clone()))?;
+            if !builder.availability_zones.is_empty() && strategy == PlacementStrategy::Cluster {
+                return Err(BuildError::InvalidConfiguration(format!("AwsBuilder::availability_zones cannot be used with the cluster placement group {name:?} since cluster placement groups are confined to a single availability zone")));
+            }
+            tracing::info!("using existing placement group {name:?}");
+            placement_strategy = Some(strategy);
+            Some(name.clone())
+        } else if let Some(strategy) = &builder.placement_strategy {
+            *phase.lock().unwrap() = "creating the placement group";
+            let placement_group = format!("aws-throwaway-{user_name}-{}", Uuid::new_v4());
+            audit!(
+                audit_log,
+                "CreatePlacementGroup",
+                group_name = placement_group,
+                strategy = strategy.as_str()
+            );
+            client
+                .create_placement_group()
+                .group_name(&placement_group)
+                .strategy(strategy.clone())
+                .tag_specifications(
+                    TagSpecification::builder()
+                        .resource_type(ResourceType::PlacementGroup)
+                        .tags(Tag::builder().key(USER_TAG_NAME).value(&user_name).build())
+                        .tags(Tag::builder().key(RUN_TAG_NAME).value(&run_token).build())
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| BuildError::from_service_error(e.into_service_error()))?;
+            tracing::info!("created placement group");
+            Some(placement_group)
+        } else {
+            None
+        };
 
         let key = PrivateKey::random(OsRng {}, ssh_key::Algorithm::Ed25519).unwrap();
         let host_public_key_bytes = key.public_key().to_bytes().unwrap();
         let host_public_key = key.public_key().to_openssh().unwrap();
         let host_private_key = key.to_openssh(ssh_key::LineEnding::LF).unwrap().to_string();
 
-        Aws {
+        Ok(Aws {
             client,
             user_name,
             keyname,
@@ -138,42 +686,112 @@ impl Aws {
             host_public_key,
             host_private_key,
             security_group,
-        }
+            placement_group,
+            placement_strategy,
+            audit_log,
+            ssh_connect_timeout: builder.ssh_connect_timeout,
+            operation_deadline: builder.operation_deadline,
+            use_public_addresses: builder.use_public_addresses,
+            poll_policy: builder.poll_policy,
+            availability_zones: builder.availability_zones,
+            ssh_client_alive: builder.ssh_client_alive,
+            replace_host_key: builder.replace_host_key,
+            run_token,
+            scope_cleanup_to_run: builder.scope_cleanup_to_run,
+            cleanup_waits_for_termination: builder.cleanup_waits_for_termination,
+            jump_host: builder.jump_host.map(Arc::new),
+            instance_type_info: Mutex::new(HashMap::new()),
+            next_availability_zone: AtomicUsize::new(0),
+        })
     }
 
     /// Call before dropping [`Aws`]
     pub async fn cleanup_resources(&self) {
-        Self::cleanup_resources_inner(&self.client, &self.user_name).await
+        let cleanup = Self::cleanup_resources_confirmed(
+            &self.client,
+            Some(&self.user_name),
+            self.cleanup_run_token(),
+            self.audit_log,
+            self.cleanup_waits_for_termination,
+            &|_, _| true,
+        );
+        if let Err(err) = within_deadline(
+            self.operation_deadline.map(|x| Instant::now() + x),
+            "cleaning up resources",
+            async {
+                cleanup.await;
+                Ok(())
+            },
+        )
+        .await
+        {
+            tracing::error!("{err}, remaining resources will be removed by the next cleanup");
+        }
+    }
+
+    /// Returns an IAM policy document in JSON granting every action aws-throwaway performs,
+    /// which can be attached to the IAM user via the IAM console or infrastructure as code tools such as terraform.
+    ///
+    /// Launching instances with [`Ec2InstanceDefinition::iam_instance_profile`] additionally requires `iam:PassRole` on the role of the profile,
+    /// this is not included since it should be restricted to the specific role.
+    pub fn required_iam_policy_json() -> String {
+        iam::required_policy_json()
     }
 
     /// Call to cleanup without constructing an [`Aws`]
     pub async fn cleanup_resources_static() {
         let config = config().await;
-        let user_name = iam::user_name(&config).await;
+        let user_name = iam::user_name(&config).await.unwrap();
         let client = aws_sdk_ec2::Client::new(&config);
         Aws::cleanup_resources_inner(&client, &user_name).await;
     }
 
+    /// When `user_name` is `None` the resources of all users are returned.
     async fn get_all_throwaway_tags(
         client: &aws_sdk_ec2::Client,
-        user_name: &str,
+        user_name: Option<&str>,
+        run_token: Option<&str>,
+        audit_log: bool,
         resource_type: &str,
     ) -> Vec<String> {
         let user_filter_name = format!("tag:{}", USER_TAG_NAME);
+        audit!(
+            audit_log,
+            "DescribeTags",
+            resource_type,
+            user_name,
+            run_token
+        );
 
-        let mut ids = vec![];
-        for tag in client
-            .describe_tags()
-            .set_filters(Some(vec![
-                Filter::builder()
+        let mut filters = vec![
+            match user_name {
+                Some(user_name) => Filter::builder()
                     .name(&user_filter_name)
                     .values(user_name)
                     .build(),
+                None => Filter::builder()
+                    .name("tag-key")
+                    .values(USER_TAG_NAME)
+                    .build(),
+            },
+            Filter::builder()
+                .name("resource-type")
+                .values(resource_type)
+                .build(),
+        ];
+        if let Some(run_token) = run_token {
+            filters.push(
                 Filter::builder()
-                    .name("resource-type")
-                    .values(resource_type)
+                    .name(format!("tag:{RUN_TAG_NAME}"))
+                    .values(run_token)
                     .build(),
-            ]))
+            );
+        }
+
+        let mut ids = vec![];
+        for tag in client
+            .describe_tags()
+            .set_filters(Some(filters))
             .send()
             .await
             .map_err(|e| e.into_service_error())
@@ -181,21 +799,324 @@ impl Aws {
             .tags()
             .unwrap()
         {
+            // a resource may be returned once for each of its tags
             if let Some(id) = tag.resource_id() {
-                ids.push(id.to_owned());
+                if !ids.iter().any(|x| x == id) {
+                    ids.push(id.to_owned());
+                }
             }
         }
         ids
     }
 
+    /// The run token to restrict cleanup to, if cleanup is scoped to this run.
+    fn cleanup_run_token(&self) -> Option<&str> {
+        self.scope_cleanup_to_run.then_some(self.run_token.as_str())
+    }
+
     pub async fn cleanup_resources_inner(client: &aws_sdk_ec2::Client, user_name: &str) {
+        Self::cleanup_resources_confirmed(client, Some(user_name), None, false, false, &|_, _| {
+            true
+        })
+        .await;
+    }
+
+    /// Cleans up the resources created by aws-throwaway for every IAM user in the account, not just the current user.
+    /// Intended for account admins recovering resources abandoned by other users, e.g. CI runs of departed team members.
+    ///
+    /// This will destroy instances that other users may be actively using, so make sure that no other aws-throwaway runs are in progress.
+    pub async fn cleanup_resources_of_all_users_static() {
+        let config = config().await;
+        let client = aws_sdk_ec2::Client::new(&config);
+        tracing::warn!("cleaning up aws-throwaway resources of all users");
+        Self::cleanup_resources_confirmed(&client, None, None, false, false, &|_, _| true).await;
+    }
+
+    /// Reconnects to an existing instance, e.g. one created by a process that has since crashed.
+    /// `keys` must be the keys of the instance as returned by [`Ec2Instance::keys`].
+    ///
+    /// The instance must be running, it will still be terminated by cleanup if it was created by aws-throwaway.
+    pub async fn attach_to_instance(
+        &self,
+        instance_id: &str,
+        keys: InstanceKeys,
+    ) -> Result<Ec2Instance, Ec2InstanceError> {
+        let host_public_key_bytes = ssh_key::PublicKey::from_openssh(&keys.host_public_key)
+            .and_then(|key| key.to_bytes())
+            .map_err(|e| {
+                Ec2InstanceError::Other(anyhow::anyhow!(e).context("Invalid host_public_key"))
+            })?;
+
+        audit!(self.audit_log, "DescribeInstances", instance_id);
+        let described = self
+            .client
+            .describe_instances()
+            .instance_ids(instance_id)
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+        let instance = described
+            .reservations()
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|reservation| reservation.instances().unwrap_or_default())
+            .next()
+            .ok_or_else(|| {
+                Ec2InstanceError::Other(anyhow::anyhow!("Instance {instance_id} does not exist"))
+            })?;
+        let private_ip = instance
+            .private_ip_address()
+            .map(|x| x.parse().unwrap())
+            .ok_or_else(|| {
+                Ec2InstanceError::Other(anyhow::anyhow!(
+                    "Instance {instance_id} has no private IP, it is probably not running"
+                ))
+            })?;
+        let public_ip = instance.public_ip_address().map(|x| x.parse().unwrap());
+        let tags = instance
+            .tags()
+            .unwrap_or_default()
+            .iter()
+            .map(|tag| {
+                (
+                    tag.key().unwrap_or_default().to_owned(),
+                    tag.value().unwrap_or_default().to_owned(),
+                )
+            })
+            .collect();
+
+        let root_device_name = instance.root_device_name().unwrap_or_default();
+        let mut block_devices = vec![];
+        let volume_ids: Vec<String> = instance
+            .block_device_mappings()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|mapping| mapping.ebs().and_then(|ebs| ebs.volume_id()))
+            .map(|id| id.to_owned())
+            .collect();
+        if !volume_ids.is_empty() {
+            audit!(self.audit_log, "DescribeVolumes", volume_ids = ?volume_ids);
+            for volume in self
+                .client
+                .describe_volumes()
+                .set_volume_ids(Some(volume_ids))
+                .send()
+                .await
+                .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?
+                .volumes()
+                .unwrap_or_default()
+            {
+                let device_name = volume
+                    .attachments()
+                    .and_then(|attachments| attachments.first())
+                    .and_then(|attachment| attachment.device())
+                    .unwrap_or_default()
+                    .to_owned();
+                block_devices.push(BlockDevice {
+                    device_name,
+                    size_gb: volume.size().unwrap_or_default() as u32,
+                    volume_type: volume.volume_type().cloned().unwrap_or(VolumeType::Gp2),
+                });
+            }
+        }
+        block_devices.sort_by_key(|device| device.device_name != root_device_name);
+
+        audit!(self.audit_log, "DescribeAddresses", instance_id);
+        let elastic_ip = self
+            .client
+            .describe_addresses()
+            .filters(
+                Filter::builder()
+                    .name("instance-id")
+                    .values(instance_id)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?
+            .addresses()
+            .and_then(|addresses| addresses.first())
+            .cloned();
+        let elastic_ip_allocation_id = elastic_ip
+            .as_ref()
+            .and_then(|address| address.allocation_id())
+            .map(|id| id.to_owned());
+        let elastic_ip = elastic_ip
+            .as_ref()
+            .and_then(|address| address.public_ip())
+            .and_then(|ip| ip.parse().ok());
+
+        Ec2Instance::new(
+            LaunchedInstance {
+                client: self.client.clone(),
+                audit_log: self.audit_log,
+                instance_id: instance_id.to_owned(),
+                public_ip,
+                private_ip,
+                private_dns_name: instance.private_dns_name().unwrap_or_default().to_owned(),
+                connect_via_public_ip: self.use_public_addresses,
+                placement: instance
+                    .placement()
+                    .map(InstancePlacement::from_aws)
+                    .unwrap_or_default(),
+                tags,
+                block_devices,
+                network_interfaces: NetworkInterface::all_from_aws(instance),
+                elastic_ip_allocation_id,
+                elastic_ip,
+                creation_timings: None,
+                ssh_connect_timeout: self.ssh_connect_timeout,
+                state_change_timeout: self.state_change_timeout(),
+            },
+            SshCredentials {
+                host_public_key_bytes,
+                client_private_key: keys.client_private_key,
+                user: keys.user,
+                jump_host: self.jump_host.clone(),
+            },
+        )
+        .await
+    }
+
+    /// Lists every instance created by aws-throwaway for the current IAM user, including those created by other processes.
+    /// Recently terminated instances are included until AWS stops reporting them.
+    pub async fn list_instances(&self) -> Vec<InstanceSummary> {
+        // Filter by tag directly instead of describing the ids from get_all_throwaway_tags,
+        // since describing an instance id that AWS has already forgotten about fails the entire request.
+        let mut summaries = vec![];
+        let mut next_token = None;
+        loop {
+            audit!(
+                self.audit_log,
+                "DescribeInstances",
+                user_name = self.user_name
+            );
+            let response = self
+                .client
+                .describe_instances()
+                .filters(
+                    Filter::builder()
+                        .name(format!("tag:{USER_TAG_NAME}"))
+                        .values(&self.user_name)
+                        .build(),
+                )
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| e.into_service_error())
+                .unwrap();
+            for reservation in response.reservations().unwrap_or_default() {
+                for instance in reservation.instances().unwrap_or_default() {
+                    summaries.push(InstanceSummary {
+                        instance_id: instance.instance_id().unwrap_or_default().to_owned(),
+                        instance_type: instance.instance_type().cloned(),
+                        state: instance.state().and_then(|x| x.name()).cloned(),
+                        public_ip: instance.public_ip_address().map(|x| x.parse().unwrap()),
+                        private_ip: instance.private_ip_address().map(|x| x.parse().unwrap()),
+                    });
+                }
+            }
+            next_token = response.next_token().map(|x| x.to_owned());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        summaries
+    }
+
+    /// Returns the resources that [`Aws::cleanup_resources`] would delete, without deleting anything.
+    pub async fn cleanup_resources_dry_run(&self) -> CleanupPlan {
+        CleanupPlan {
+            instances: Self::get_all_throwaway_tags(
+                &self.client,
+                Some(&self.user_name),
+                self.cleanup_run_token(),
+                self.audit_log,
+                "instance",
+            )
+            .await,
+            security_groups: Self::get_all_throwaway_tags(
+                &self.client,
+                Some(&self.user_name),
+                self.cleanup_run_token(),
+                self.audit_log,
+                "security-group",
+            )
+            .await,
+            placement_groups: Self::get_all_throwaway_tags(
+                &self.client,
+                Some(&self.user_name),
+                self.cleanup_run_token(),
+                self.audit_log,
+                "placement-group",
+            )
+            .await,
+            key_pairs: Self::get_all_throwaway_tags(
+                &self.client,
+                Some(&self.user_name),
+                self.cleanup_run_token(),
+                self.audit_log,
+                "key-pair",
+            )
+            .await,
+        }
+    }
+
+    /// Like [`Aws::cleanup_resources`] but calls `confirm` with the id and type of each resource before deleting it.
+    /// A resource is only deleted if `confirm` returns true.
+    ///
+    /// Returns the resources that were skipped and so still exist.
+    pub async fn cleanup_resources_with_confirm(
+        &self,
+        confirm: impl Fn(&str, &ResourceType) -> bool,
+    ) -> Vec<(String, ResourceType)> {
+        Self::cleanup_resources_confirmed(
+            &self.client,
+            Some(&self.user_name),
+            self.cleanup_run_token(),
+            self.audit_log,
+            self.cleanup_waits_for_termination,
+            &confirm,
+        )
+        .await
+    }
+
+    /// When `run_token` is set only resources created by that run are cleaned up.
+    /// When `user_name` is `None` the resources of all users are cleaned up.
+    async fn cleanup_resources_confirmed(
+        client: &aws_sdk_ec2::Client,
+        user_name: Option<&str>,
+        run_token: Option<&str>,
+        audit_log: bool,
+        wait_for_termination: bool,
+        confirm: &dyn Fn(&str, &ResourceType) -> bool,
+    ) -> Vec<(String, ResourceType)> {
+        let mut skipped = vec![];
+        let mut confirmed = |ids: Vec<String>, resource_type: ResourceType| -> Vec<String> {
+            ids.into_iter()
+                .filter(|id| {
+                    if confirm(id, &resource_type) {
+                        true
+                    } else {
+                        tracing::info!("skipping deletion of {resource_type:?} {id:?}");
+                        skipped.push((id.clone(), resource_type.clone()));
+                        false
+                    }
+                })
+                .collect()
+        };
+
         // delete instances
         tracing::info!("Terminating instances");
-        let instance_ids = Self::get_all_throwaway_tags(client, user_name, "instance").await;
+        let instance_ids = confirmed(
+            Self::get_all_throwaway_tags(client, user_name, run_token, audit_log, "instance").await,
+            ResourceType::Instance,
+        );
         if !instance_ids.is_empty() {
+            audit!(audit_log, "TerminateInstances", instance_ids = ?instance_ids);
             for result in client
                 .terminate_instances()
-                .set_instance_ids(Some(instance_ids))
+                .set_instance_ids(Some(instance_ids.clone()))
                 .send()
                 .await
                 .map_err(|e| e.into_service_error())
@@ -210,10 +1131,18 @@ impl Aws {
                     result.current_state().unwrap().name().unwrap()
                 );
             }
+            if wait_for_termination {
+                Self::wait_for_instances_terminated(client, &instance_ids, audit_log).await;
+            }
         }
 
         // delete security groups
-        for id in Self::get_all_throwaway_tags(client, user_name, "security-group").await {
+        for id in confirmed(
+            Self::get_all_throwaway_tags(client, user_name, run_token, audit_log, "security-group")
+                .await,
+            ResourceType::SecurityGroup,
+        ) {
+            audit!(audit_log, "DeleteSecurityGroup", group_id = id);
             if let Err(err) = client.delete_security_group().group_id(&id).send().await {
                 tracing::info!(
                     "security group {id:?} could not be deleted, this will get cleaned up eventually on a future aws-throwaway cleanup: {:?}",
@@ -224,8 +1153,55 @@ impl Aws {
             }
         }
 
+        // delete placement groups
+        for id in confirmed(
+            Self::get_all_throwaway_tags(
+                client,
+                user_name,
+                run_token,
+                audit_log,
+                "placement-group",
+            )
+            .await,
+            ResourceType::PlacementGroup,
+        ) {
+            audit!(audit_log, "DescribePlacementGroups", group_id = id);
+            let groups = client
+                .describe_placement_groups()
+                .group_ids(&id)
+                .send()
+                .await
+                .map_err(|e| e.into_service_error())
+                .unwrap();
+            for name in groups
+                .placement_groups()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|x| x.group_name())
+            {
+                audit!(audit_log, "DeletePlacementGroup", group_name = name);
+                if let Err(err) = client
+                    .delete_placement_group()
+                    .group_name(name)
+                    .send()
+                    .await
+                {
+                    tracing::info!(
+                        "placement group {name:?} could not be deleted, this will get cleaned up eventually on a future aws-throwaway cleanup: {:?}",
+                        err.into_service_error().meta().message()
+                    )
+                } else {
+                    tracing::info!("placement group {name:?} was succesfully deleted",)
+                }
+            }
+        }
+
         // delete keypairs
-        for id in Self::get_all_throwaway_tags(client, user_name, "key-pair").await {
+        for id in confirmed(
+            Self::get_all_throwaway_tags(client, user_name, run_token, audit_log, "key-pair").await,
+            ResourceType::KeyPair,
+        ) {
+            audit!(audit_log, "DeleteKeyPair", key_pair_id = id);
             client
                 .delete_key_pair()
                 .key_pair_id(&id)
@@ -238,144 +1214,1011 @@ impl Aws {
                 .unwrap();
             tracing::info!("keypair {id:?} was succesfully deleted");
         }
+
+        skipped
     }
 
-    pub async fn create_ec2_instance(
+    /// Security groups cannot be deleted while referenced by an instance that is still shutting down,
+    /// so poll until every instance has terminated or a timeout is reached.
+    async fn wait_for_instances_terminated(
+        client: &aws_sdk_ec2::Client,
+        instance_ids: &[String],
+        audit_log: bool,
+    ) {
+        tracing::info!("Waiting for instances to terminate");
+        let deadline = Instant::now() + Duration::from_secs(300);
+        loop {
+            audit!(audit_log, "DescribeInstances", instance_ids = ?instance_ids);
+            let output = client
+                .describe_instances()
+                .set_instance_ids(Some(instance_ids.to_vec()))
+                .send()
+                .await
+                .map_err(|e| e.into_service_error())
+                .unwrap();
+            let all_terminated = output
+                .reservations()
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|reservation| reservation.instances().unwrap_or_default())
+                .all(|instance| {
+                    instance.state().and_then(|state| state.name())
+                        == Some(&InstanceStateName::Terminated)
+                });
+            if all_terminated {
+                return;
+            }
+            if Instant::now() > deadline {
+                tracing::warn!("Instances did not terminate within 300s, security groups may fail to be deleted");
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    /// Looks up the specifications of `instance_type`, e.g. to pick the smallest type meeting a memory requirement.
+    /// Results are cached for the lifetime of the [`Aws`] so repeated calls do not hit the EC2 API.
+    pub async fn instance_type_info(
         &self,
         instance_type: InstanceType,
-        storage_gb: u32,
-    ) -> Ec2Instance {
+    ) -> Result<InstanceTypeInfo, Ec2InstanceError> {
+        if let Some(info) = self.instance_type_info.lock().unwrap().get(&instance_type) {
+            return Ok(info.clone());
+        }
+
+        audit!(
+            self.audit_log,
+            "DescribeInstanceTypes",
+            instance_type = instance_type.as_str()
+        );
+        let types = self
+            .client
+            .describe_instance_types()
+            .instance_types(instance_type.clone())
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+        let info = types
+            .instance_types()
+            .and_then(|types| types.first())
+            .ok_or_else(|| {
+                Ec2InstanceError::InvalidDefinition(format!(
+                    "The instance type {} does not exist",
+                    instance_type.as_str()
+                ))
+            })?;
+        let info = InstanceTypeInfo {
+            vcpus: info
+                .v_cpu_info()
+                .and_then(|x| x.default_v_cpus())
+                .unwrap_or_default() as u32,
+            memory_mib: info
+                .memory_info()
+                .and_then(|x| x.size_in_mi_b())
+                .unwrap_or_default() as u64,
+            network_performance: info
+                .network_info()
+                .and_then(|x| x.network_performance())
+                .unwrap_or_default()
+                .to_owned(),
+            cpu_arch: info
+                .processor_info()
+                .and_then(|x| x.supported_architectures())
+                .unwrap_or_default()
+                .iter()
+                .find_map(|arch| match arch {
+                    ArchitectureType::Arm64 => Some(CpuArch::Aarch64),
+                    ArchitectureType::X8664 => Some(CpuArch::X86_64),
+                    _ => None,
+                }),
+            placement_strategies: info
+                .placement_group_info()
+                .and_then(|x| x.supported_strategies())
+                .unwrap_or_default()
+                .iter()
+                .map(|strategy| PlacementStrategy::from(strategy.as_str()))
+                .collect(),
+        };
+        self.instance_type_info
+            .lock()
+            .unwrap()
+            .insert(instance_type, info.clone());
+        Ok(info)
+    }
+
+    async fn validate_hibernation(
+        &self,
+        definition: &Ec2InstanceDefinition,
+    ) -> Result<(), Ec2InstanceError> {
+        // AWS does not support hibernating instances with more than 150GiB of RAM
+        const MAX_MEMORY_MIB: u64 = 150 * 1024;
+        // space left for the OS on top of the hibernated RAM
+        const OS_SIZE_GB: u64 = 8;
+
+        let memory_mib = self
+            .instance_type_info(definition.instance_type.clone())
+            .await?
+            .memory_mib;
+        if memory_mib > MAX_MEMORY_MIB {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "hibernation was enabled but instance type {} has more than the supported 150GiB of memory",
+                definition.instance_type.as_str()
+            )));
+        }
+        let required_gb = (memory_mib + 1023) / 1024 + OS_SIZE_GB;
+        if (definition.volume_size_gb as u64) < required_gb {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "hibernation was enabled but the root volume of {}GB is too small to hold the memory of instance type {}, it must be at least {required_gb}GB",
+                definition.volume_size_gb,
+                definition.instance_type.as_str()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn validate_ebs_optimized_support(
+        &self,
+        instance_type: &InstanceType,
+    ) -> Result<(), Ec2InstanceError> {
+        audit!(
+            self.audit_log,
+            "DescribeInstanceTypes",
+            instance_type = instance_type.as_str()
+        );
+        let types = self
+            .client
+            .describe_instance_types()
+            .instance_types(instance_type.clone())
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+        let support = types
+            .instance_types()
+            .and_then(|types| types.first())
+            .and_then(|info| info.ebs_info())
+            .and_then(|ebs| ebs.ebs_optimized_support());
+        if support == Some(&EbsOptimizedSupport::Unsupported) {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "ebs_optimized was enabled but instance type {} does not support EBS optimization",
+                instance_type.as_str()
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn create_ec2_instance(
+        &self,
+        definition: Ec2InstanceDefinition,
+    ) -> Result<Ec2Instance, Ec2InstanceError> {
+        Ok(self
+            .create_ec2_instances(vec![definition])
+            .await?
+            .pop()
+            .unwrap())
+    }
+
+    /// Creates an instance for each definition, returning the instances in the same order as the definitions.
+    ///
+    /// Instances are launched concurrently and identical definitions are launched together in a single request,
+    /// so this is much faster than calling [`Aws::create_ec2_instance`] for each definition in turn.
+    /// All definitions are validated before any instances are launched.
+    pub async fn create_ec2_instances(
+        &self,
+        definitions: Vec<Ec2InstanceDefinition>,
+    ) -> Result<Vec<Ec2Instance>, Ec2InstanceError> {
+        let mut groups: Vec<(Ec2InstanceDefinition, Vec<usize>)> = vec![];
+        for (index, definition) in definitions.into_iter().enumerate() {
+            match groups.iter_mut().find(|(group, _)| *group == definition) {
+                Some((_, indexes)) => indexes.push(index),
+                None => groups.push((definition, vec![index])),
+            }
+        }
+
+        let deadline = self.operation_deadline.map(|x| Instant::now() + x);
+        within_deadline(deadline, "validating the instance definitions", async {
+            let mut elastic_ips = vec![];
+            for (definition, indexes) in &groups {
+                definition.validate()?;
+                self.validate_tenancy(definition)?;
+                if definition.ebs_optimized == Some(true) {
+                    self.validate_ebs_optimized_support(&definition.instance_type)
+                        .await?;
+                }
+                if definition.hibernation {
+                    self.validate_hibernation(definition).await?;
+                }
+                if let Some(allocation_id) = &definition.elastic_ip_allocation_id {
+                    if indexes.len() > 1 || elastic_ips.contains(&allocation_id) {
+                        return Err(Ec2InstanceError::InvalidDefinition(format!(
+                            "The elastic IP {allocation_id} was assigned to multiple instances"
+                        )));
+                    }
+                    elastic_ips.push(allocation_id);
+                    self.validate_elastic_ip(allocation_id).await?;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+
+        // If the deadline is exceeded while launching, the ids of any instances that were launched are unknown,
+        // they will instead be terminated by the next cleanup.
+        let launched = within_deadline(
+            deadline,
+            "launching instances",
+            try_join_all(groups.iter().map(|(definition, indexes)| async {
+                let start = Instant::now();
+                let instance_ids = self.launch_instances(definition, indexes.len()).await?;
+                Ok::<_, Ec2InstanceError>((instance_ids, start.elapsed()))
+            })),
+        )
+        .await?;
+        let launched_ids: Vec<String> = launched
+            .iter()
+            .flat_map(|(instance_ids, _)| instance_ids)
+            .cloned()
+            .collect();
+
+        let mut pending = vec![];
+        for ((definition, indexes), (instance_ids, launch)) in groups.iter().zip(launched) {
+            for (index, instance_id) in indexes.iter().zip(instance_ids) {
+                pending.push((*index, definition, instance_id, launch));
+            }
+        }
+        pending.sort_by_key(|(index, _, _, _)| *index);
+
+        let result = within_deadline(
+            deadline,
+            "waiting for instances to become reachable",
+            async {
+                // Assigning IPs and then associating any elastic IPs share a single poll timeout.
+                let address_deadline = Instant::now() + self.poll_policy.timeout;
+                let mut addresses = self
+                    .wait_for_addresses(
+                        pending
+                            .iter()
+                            .map(|(_, definition, instance_id, _)| {
+                                (instance_id.clone(), self.connect_via_public_ip(definition))
+                            })
+                            .collect(),
+                        address_deadline,
+                    )
+                    .await?;
+                try_join_all(
+                    pending
+                        .into_iter()
+                        .map(|(_, definition, instance_id, launch)| {
+                            let addresses = addresses.remove(&instance_id).unwrap();
+                            self.connect_instance(
+                                definition,
+                                instance_id,
+                                launch,
+                                addresses,
+                                address_deadline,
+                            )
+                        }),
+                )
+                .await
+            },
+        )
+        .await;
+        if let Err(Ec2InstanceError::DeadlineExceeded { .. }) = &result {
+            self.terminate_abandoned_instances(launched_ids).await;
+        }
+        result
+    }
+
+    /// Terminates the specified instances without waiting for them to shut down.
+    pub(crate) async fn terminate_instances(
+        &self,
+        instance_ids: Vec<String>,
+    ) -> Result<(), Ec2InstanceError> {
+        audit!(self.audit_log, "TerminateInstances", instance_ids = ?instance_ids);
+        self.client
+            .terminate_instances()
+            .set_instance_ids(Some(instance_ids))
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+        Ok(())
+    }
+
+    /// Terminates instances launched by an operation that exceeded its deadline.
+    /// Failures are only logged since the instances will also be terminated by the next cleanup.
+    async fn terminate_abandoned_instances(&self, instance_ids: Vec<String>) {
+        audit!(self.audit_log, "TerminateInstances", instance_ids = ?instance_ids);
+        if let Err(err) = self
+            .client
+            .terminate_instances()
+            .set_instance_ids(Some(instance_ids))
+            .send()
+            .await
+        {
+            tracing::warn!(
+                "Failed to terminate instances after exceeding the operation deadline, they will be terminated by the next cleanup: {:?}",
+                err.into_service_error().meta().message()
+            );
+        }
+    }
+
+    fn instance_tags(&self, definition: &Ec2InstanceDefinition) -> Vec<(String, String)> {
+        let mut tags = vec![("Name".to_owned(), "aws-throwaway".to_owned())];
+        for (key, value) in &definition.tags {
+            if key == "Name" {
+                tags[0].1 = value.clone();
+            } else {
+                tags.push((key.clone(), value.clone()));
+            }
+        }
+        tags.push((USER_TAG_NAME.to_owned(), self.user_name.clone()));
+        tags.push((RUN_TAG_NAME.to_owned(), self.run_token.clone()));
+        tags
+    }
+
+    fn validate_tenancy(&self, definition: &Ec2InstanceDefinition) -> Result<(), Ec2InstanceError> {
+        match (&definition.tenancy, &self.placement_strategy) {
+            (Some(Tenancy::Host), Some(strategy)) => {
+                Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "Instances with host tenancy cannot be launched into a placement group but a {} placement strategy was configured via AwsBuilder::placement_strategy",
+                    strategy.as_str()
+                )))
+            }
+            (Some(Tenancy::Dedicated), Some(PlacementStrategy::Spread)) => {
+                Err(Ec2InstanceError::InvalidDefinition(
+                    "Instances with dedicated tenancy cannot be launched into a spread placement group, use a cluster or partition placement strategy instead".to_owned(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn validate_elastic_ip(&self, allocation_id: &str) -> Result<(), Ec2InstanceError> {
+        audit!(self.audit_log, "DescribeAddresses", allocation_id);
+        let addresses = self
+            .client
+            .describe_addresses()
+            .allocation_ids(allocation_id)
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+        match addresses
+            .addresses()
+            .and_then(|addresses| addresses.first())
+        {
+            Some(address) if address.association_id().is_some() => {
+                Err(Ec2InstanceError::InvalidDefinition(format!(
+                    "The elastic IP {allocation_id} is already associated with {}",
+                    address
+                        .instance_id()
+                        .or(address.network_interface_id())
+                        .unwrap_or("another resource")
+                )))
+            }
+            Some(_) => Ok(()),
+            None => Err(Ec2InstanceError::InvalidDefinition(format!(
+                "The elastic IP {allocation_id} does not exist"
+            ))),
+        }
+    }
+
+    /// Associates the elastic IP with the instance, returning the public IP of the elastic IP.
+    async fn associate_elastic_ip(
+        &self,
+        instance_id: &str,
+        allocation_id: &str,
+        deadline: Instant,
+    ) -> Result<IpAddr, Ec2InstanceError> {
+        let mut interval = self.poll_policy.initial_interval;
+        loop {
+            audit!(
+                self.audit_log,
+                "AssociateAddress",
+                instance_id,
+                allocation_id
+            );
+            match self
+                .client
+                .associate_address()
+                .instance_id(instance_id)
+                .allocation_id(allocation_id)
+                .allow_reassociation(false)
+                .send()
+                .await
+            {
+                Ok(_) => break,
+                Err(e) => {
+                    let err = e.into_service_error();
+                    // The instance may still be pending, in which case the association will succeed once it is running.
+                    if err.code() == Some("IncorrectInstanceState") && Instant::now() < deadline {
+                        tracing::info!(
+                            "instance {instance_id} is not yet ready for its elastic IP to be associated, retrying"
+                        );
+                        tokio::time::sleep(interval).await;
+                        interval = self.poll_policy.next_interval(interval);
+                    } else {
+                        return Err(Ec2InstanceError::from_service_error(err));
+                    }
+                }
+            }
+        }
+
+        audit!(self.audit_log, "DescribeAddresses", allocation_id);
+        self.client
+            .describe_addresses()
+            .allocation_ids(allocation_id)
+            .send()
+            .await
+            .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?
+            .addresses()
+            .and_then(|addresses| addresses.first())
+            .and_then(|address| address.public_ip())
+            .and_then(|ip| ip.parse().ok())
+            .ok_or_else(|| {
+                Ec2InstanceError::Other(anyhow::anyhow!(
+                    "The elastic IP {allocation_id} has no public IP"
+                ))
+            })
+    }
+
+    /// Launches `count` instances of the definition, returning their instance ids.
+    /// The instances are spread round-robin across the configured availability zones if any, with one request made per zone.
+    async fn launch_instances(
+        &self,
+        definition: &Ec2InstanceDefinition,
+        count: usize,
+    ) -> Result<Vec<String>, Ec2InstanceError> {
+        if self.availability_zones.is_empty() {
+            return self.launch_instances_in_zone(definition, count, None).await;
+        }
+
+        let first = self
+            .next_availability_zone
+            .fetch_add(count, Ordering::Relaxed);
+        let mut counts = vec![0; self.availability_zones.len()];
+        for i in first..first + count {
+            counts[i % self.availability_zones.len()] += 1;
+        }
+        let launched = try_join_all(
+            self.availability_zones
+                .iter()
+                .zip(counts)
+                .filter(|(_, count)| *count > 0)
+                .map(|(zone, count)| self.launch_instances_in_zone(definition, count, Some(zone))),
+        )
+        .await?;
+        Ok(launched.into_iter().flatten().collect())
+    }
+
+    async fn launch_instances_in_zone(
+        &self,
+        definition: &Ec2InstanceDefinition,
+        count: usize,
+        availability_zone: Option<&str>,
+    ) -> Result<Vec<String>, Ec2InstanceError> {
+        let tags = self.instance_tags(definition);
+        let aws_tags: Vec<Tag> = tags
+            .iter()
+            .map(|(key, value)| Tag::builder().key(key).value(value).build())
+            .collect();
+
+        let arch = definition.resolved_cpu_arch()?;
+        let image_id = definition.image_id(arch);
+        let (root_encrypted, root_kms_key_id) = definition.root_volume_encryption();
+        let mut block_device_mappings = vec![BlockDeviceMapping::builder()
+            .device_name(definition.resolved_root_device_name())
+            .ebs(
+                EbsBlockDevice::builder()
+                    .delete_on_termination(true)
+                    .set_encrypted(root_encrypted)
+                    .set_kms_key_id(root_kms_key_id)
+                    .volume_size(definition.volume_size_gb as i32)
+                    .volume_type(definition.volume_type.clone())
+                    .set_iops(definition.root_volume_iops())
+                    .set_throughput(definition.volume_throughput)
+                    .build(),
+            )
+            .build()];
+        for volume in &definition.additional_volumes {
+            block_device_mappings.push(
+                BlockDeviceMapping::builder()
+                    .device_name(&volume.device_name)
+                    .ebs(
+                        EbsBlockDevice::builder()
+                            .delete_on_termination(true)
+                            .set_encrypted(definition.volume_encryption.is_some().then_some(true))
+                            .set_kms_key_id(definition.volume_encryption.clone().flatten())
+                            .volume_size(volume.size_gb as i32)
+                            .volume_type(volume.volume_type.clone())
+                            .build(),
+                    )
+                    .build(),
+            );
+        }
+        let ssh_service = definition.os.ssh_service();
+        // The host key must be setup before any user provided script runs, so that a failure in the users script cannot prevent us from connecting.
+        let host_key_setup = if self.replace_host_key {
+            format!(
+                r#"echo "{}" > /etc/ssh/ssh_host_ed25519_key.pub
+echo "{}" > /etc/ssh/ssh_host_ed25519_key
+chmod 600 /etc/ssh/ssh_host_ed25519_key
+"#,
+                self.host_public_key, self.host_private_key
+            )
+        } else {
+            String::new()
+        };
+        let mut user_data = format!(
+            r#"#!/bin/bash
+sudo systemctl stop {ssh_service}
+{host_key_setup}
+echo "ClientAliveInterval {}" >> /etc/ssh/sshd_config
+echo "ClientAliveCountMax {}" >> /etc/ssh/sshd_config
+sudo systemctl start {ssh_service}
+"#,
+            self.ssh_client_alive.0.as_secs(),
+            self.ssh_client_alive.1
+        );
+        if let Some(hostname) = &definition.hostname {
+            // The hostname has been validated so it is safe to insert into the script unescaped.
+            user_data.push_str(&format!(
+                r#"hostnamectl set-hostname {hostname}
+echo "127.0.1.1 {hostname}" >> /etc/hosts
+echo "preserve_hostname: true" > /etc/cloud/cloud.cfg.d/99-aws-throwaway-hostname.cfg
+"#
+            ));
+        }
+        if let Some(ttl) = definition.auto_terminate_after {
+            // Scheduled before the user's script so that a failure in it cannot prevent the instance from terminating.
+            let minutes = (ttl.as_secs() + 59) / 60;
+            user_data.push_str(&format!("shutdown -h +{minutes}\n"));
+        }
+        if let Some(extra) = &definition.user_data {
+            user_data.push_str(extra);
+            user_data.push('\n');
+        }
+        if user_data.len() > MAX_USER_DATA_BYTES {
+            return Err(Ec2InstanceError::InvalidDefinition(format!(
+                "user_data is {} bytes but combined with the host key setup it must not exceed {MAX_USER_DATA_BYTES} bytes",
+                definition.user_data.as_ref().map(|x| x.len()).unwrap_or_default()
+            )));
+        }
+        audit!(
+            self.audit_log,
+            "RunInstances",
+            instance_type = definition.instance_type.as_str(),
+            count,
+            image_id,
+            availability_zone,
+            placement_group = ?self.placement_group,
+            capacity_reservation_id = ?definition.capacity_reservation_id,
+            tags = ?tags,
+            user_data = "<redacted>"
+        );
+
         let result = self
             .client
             .run_instances()
-            .instance_type(instance_type.clone())
-            .min_count(1)
-            .max_count(1)
-            .block_device_mappings(
-                BlockDeviceMapping::builder().device_name("/dev/sda1").ebs(
-                    EbsBlockDevice::builder()
-                        .delete_on_termination(true)
-                        .volume_size(storage_gb as i32)
-                        .volume_type(VolumeType::Gp2)
+            .instance_type(definition.instance_type.clone())
+            .set_placement(
+                if self.placement_group.is_some()
+                    || definition.tenancy.is_some()
+                    || availability_zone.is_some()
+                {
+                    Some(
+                        Placement::builder()
+                            .set_group_name(self.placement_group.clone())
+                            .set_tenancy(definition.tenancy.clone())
+                            .set_availability_zone(availability_zone.map(|x| x.to_owned()))
+                            .build(),
+                    )
+                } else {
+                    None
+                },
+            )
+            .set_ebs_optimized(definition.ebs_optimized)
+            .set_instance_initiated_shutdown_behavior(
+                definition
+                    .auto_terminate_after
+                    .map(|_| ShutdownBehavior::Terminate),
+            )
+            .monitoring(
+                RunInstancesMonitoringEnabled::builder()
+                    .enabled(definition.detailed_monitoring)
+                    .build(),
+            )
+            .set_hibernation_options(definition.hibernation.then(|| {
+                HibernationOptionsRequest::builder()
+                    .configured(true)
+                    .build()
+            }))
+            .set_ipv6_address_count(definition.ipv6_address_count.map(|x| x as i32))
+            .set_capacity_reservation_specification(
+                definition.capacity_reservation_id.as_ref().map(|id| {
+                    CapacityReservationSpecification::builder()
+                        .capacity_reservation_target(
+                            CapacityReservationTarget::builder()
+                                .capacity_reservation_id(id)
+                                .build(),
+                        )
                         .build()
-                ).build()
+                }),
             )
+            .metadata_options(
+                InstanceMetadataOptionsRequest::builder()
+                    .http_tokens(definition.metadata_http_tokens.clone())
+                    .http_put_response_hop_limit(definition.metadata_hop_limit as i32)
+                    .build(),
+            )
+            .set_private_dns_name_options(definition.private_dns_hostname_type.as_ref().map(
+                |hostname_type| {
+                    PrivateDnsNameOptionsRequest::builder()
+                        .hostname_type(hostname_type.clone())
+                        .build()
+                },
+            ))
+            .set_iam_instance_profile(definition.iam_instance_profile.as_ref().map(|profile| {
+                if profile.starts_with("arn:") {
+                    IamInstanceProfileSpecification::builder()
+                        .arn(profile)
+                        .build()
+                } else {
+                    IamInstanceProfileSpecification::builder()
+                        .name(profile)
+                        .build()
+                }
+            }))
+            .min_count(count as i32)
+            .max_count(count as i32)
+            .set_block_device_mappings(Some(block_device_mappings))
             .security_groups(&self.security_group)
-            .key_name(&self.keyname)
-            .user_data(base64::engine::general_purpose::STANDARD.encode(format!(
-                r#"#!/bin/bash
-sudo systemctl stop ssh
-echo "{}" > /etc/ssh/ssh_host_ed25519_key.pub
-echo "{}" > /etc/ssh/ssh_host_ed25519_key
-
-echo "ClientAliveInterval 30" >> /etc/ssh/sshd_config
-sudo systemctl start ssh
-            "#,
-                self.host_public_key, self.host_private_key
-            )))
+            .key_name(
+                definition
+                    .existing_key_pair
+                    .as_ref()
+                    .map(|key_pair| &key_pair.key_name)
+                    .unwrap_or(&self.keyname),
+            )
+            .user_data(base64::engine::general_purpose::STANDARD.encode(user_data))
             .tag_specifications(
                 TagSpecification::builder()
                     .resource_type(ResourceType::Instance)
-                    .set_tags(Some(vec![
-                        Tag::builder().key("Name").value("aws-throwaway").build(),
-                        Tag::builder()
-                            .key(USER_TAG_NAME)
-                            .value(&self.user_name)
-                            .build(),
-                    ]))
+                    .set_tags(Some(aws_tags.clone()))
                     .build(),
             )
-            .image_id(format!(
-                "resolve:ssm:/aws/service/canonical/ubuntu/server/22.04/stable/current/{}/hvm/ebs-gp2/ami-id",
-                get_arch_of_instance_type(instance_type).get_ubuntu_arch_identifier()
-            ))
+            .tag_specifications(
+                TagSpecification::builder()
+                    .resource_type(ResourceType::Volume)
+                    .set_tags(Some(aws_tags))
+                    .build(),
+            )
+            .image_id(image_id)
             .send()
             .await
-            .map_err(|e| e.into_service_error())
-            .unwrap();
-        let instance_id = result
+            .map_err(|e| {
+                let err = e.into_service_error();
+                let message = err.message().unwrap_or_default().to_owned();
+                match (&self.placement_strategy, err.code()) {
+                    // These codes are also used for unrelated problems, e.g. an instance type that does not support the AMI,
+                    // so only blame the placement strategy when AWS does.
+                    (Some(strategy), Some("InvalidParameterCombination" | "Unsupported"))
+                        if message.to_lowercase().contains("placement") =>
+                    {
+                        Ec2InstanceError::UnsupportedPlacement {
+                            strategy: strategy.clone(),
+                            message,
+                        }
+                    }
+                    _ => Ec2InstanceError::from_service_error(err),
+                }
+            })?;
+        let instance_ids: Vec<String> = result
             .instances()
-            .unwrap()
+            .unwrap_or_default()
             .iter()
-            .next()
-            .unwrap()
-            .instance_id()
-            .unwrap()
-            .to_owned();
+            .filter_map(|instance| instance.instance_id().map(|id| id.to_owned()))
+            .collect();
+        if instance_ids.len() != count {
+            return Err(Ec2InstanceError::Other(anyhow::anyhow!(
+                "run_instances returned {} instance ids but {count} instances were requested",
+                instance_ids.len()
+            )));
+        }
+        Ok(instance_ids)
+    }
+
+    /// How long an [`Ec2Instance`] waits for the instance to reach a new state
+    fn state_change_timeout(&self) -> Duration {
+        self.operation_deadline.unwrap_or(self.poll_policy.timeout)
+    }
 
-        let mut public_ip = None;
-        let mut private_ip = None;
+    fn connect_via_public_ip(&self, definition: &Ec2InstanceDefinition) -> bool {
+        definition
+            .connect_via_public_ip
+            .unwrap_or(self.use_public_addresses)
+    }
+
+    /// Reads the ed25519 host key that cloud-init prints to the console of the instance during boot.
+    /// The console output is only captured periodically by AWS, so this can take a few minutes.
+    async fn host_key_from_console_output(
+        &self,
+        instance_id: &str,
+    ) -> Result<Vec<u8>, Ec2InstanceError> {
+        let deadline = Instant::now() + self.ssh_connect_timeout;
+        loop {
+            audit!(self.audit_log, "GetConsoleOutput", instance_id);
+            let output = self
+                .client
+                .get_console_output()
+                .instance_id(instance_id)
+                .send()
+                .await
+                .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?;
+            let output = output
+                .output()
+                .and_then(|encoded| {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(encoded)
+                        .ok()
+                })
+                .map(|decoded| String::from_utf8_lossy(&decoded).into_owned())
+                .unwrap_or_default();
+            let host_key = output
+                .split("-----BEGIN SSH HOST KEY KEYS-----")
+                .nth(1)
+                .and_then(|keys| keys.split("-----END SSH HOST KEY KEYS-----").next())
+                .and_then(|keys| {
+                    keys.lines()
+                        .map(|line| line.trim())
+                        .find(|line| line.starts_with("ssh-ed25519 "))
+                });
+            if let Some(host_key) = host_key {
+                return ssh_key::PublicKey::from_openssh(host_key)
+                    .and_then(|key| key.to_bytes())
+                    .map_err(|e| {
+                        Ec2InstanceError::Other(
+                            anyhow::anyhow!(e).context("Invalid host key in console output"),
+                        )
+                    });
+            }
+            if Instant::now() > deadline {
+                return Err(Ec2InstanceError::SshConnectTimeout {
+                    instance_id: instance_id.to_owned(),
+                    source: anyhow::anyhow!(
+                        "The instance did not print an ed25519 host key to its console within {:?}",
+                        self.ssh_connect_timeout
+                    ),
+                });
+            }
+            tracing::info!(
+                "waiting for instance {instance_id} to print its host key to the console"
+            );
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
 
-        while public_ip.is_none() || private_ip.is_none() {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    /// Waits for launched instances to be assigned their IP addresses, polling all instances that are still waiting with a single request.
+    /// Each instance is given alongside whether it needs a public IP.
+    async fn wait_for_addresses(
+        &self,
+        mut waiting: Vec<(String, bool)>,
+        deadline: Instant,
+    ) -> Result<HashMap<String, AssignedAddresses>, Ec2InstanceError> {
+        let mut assigned = HashMap::new();
+        let start = Instant::now();
+        let mut interval = self.poll_policy.initial_interval;
+        while !waiting.is_empty() {
+            if Instant::now() > deadline {
+                return Err(Ec2InstanceError::IpAssignmentTimeout {
+                    instance_id: waiting.swap_remove(0).0,
+                });
+            }
+            tokio::time::sleep(interval).await;
+            interval = self.poll_policy.next_interval(interval);
+            let instance_ids: Vec<String> = waiting.iter().map(|(id, _)| id.clone()).collect();
+            audit!(self.audit_log, "DescribeInstances", instance_ids = ?instance_ids);
             for reservation in self
                 .client
                 .describe_instances()
-                .instance_ids(&instance_id)
+                .set_instance_ids(Some(instance_ids))
                 .send()
                 .await
-                .map_err(|e| e.into_service_error())
-                .unwrap()
+                .map_err(|e| Ec2InstanceError::from_service_error(e.into_service_error()))?
                 .reservations()
-                .unwrap()
+                .unwrap_or_default()
             {
-                for instance in reservation.instances().unwrap() {
-                    public_ip = instance.public_ip_address().map(|x| x.parse().unwrap());
-                    private_ip = instance.private_ip_address().map(|x| x.parse().unwrap());
+                for instance in reservation.instances().unwrap_or_default() {
+                    let Some(index) = waiting
+                        .iter()
+                        .position(|(id, _)| Some(id.as_str()) == instance.instance_id())
+                    else {
+                        continue;
+                    };
+                    // e.g. the capacity reservation or spot capacity the instance launched into was exhausted
+                    if let Some(InstanceStateName::ShuttingDown | InstanceStateName::Terminated) =
+                        instance.state().and_then(|state| state.name())
+                    {
+                        let reason = instance.state_reason();
+                        let message = format!(
+                            "instance {} was terminated while launching: {}",
+                            waiting[index].0,
+                            reason.and_then(|x| x.message()).unwrap_or_default()
+                        );
+                        return Err(match reason.and_then(|x| x.code()) {
+                            Some("Server.InsufficientInstanceCapacity") => {
+                                Ec2InstanceError::InsufficientCapacity(message)
+                            }
+                            _ => Ec2InstanceError::Other(anyhow::anyhow!(message)),
+                        });
+                    }
+                    let public_ip = instance.public_ip_address().map(|x| x.parse().unwrap());
+                    let private_ip = instance.private_ip_address().map(|x| x.parse().unwrap());
+                    let needs_public_ip = waiting[index].1;
+                    if let Some(private_ip) = private_ip {
+                        if public_ip.is_some() || !needs_public_ip {
+                            let (instance_id, _) = waiting.swap_remove(index);
+                            assigned.insert(
+                                instance_id,
+                                AssignedAddresses {
+                                    public_ip,
+                                    private_ip,
+                                    private_dns_name: instance
+                                        .private_dns_name()
+                                        .unwrap_or_default()
+                                        .to_owned(),
+                                    placement: instance
+                                        .placement()
+                                        .map(InstancePlacement::from_aws)
+                                        .unwrap_or_default(),
+                                    network_interfaces: NetworkInterface::all_from_aws(instance),
+                                    assigned_after: start.elapsed(),
+                                },
+                            );
+                        }
+                    }
                 }
             }
         }
-        let public_ip = public_ip.unwrap();
-        let private_ip = private_ip.unwrap();
-        tracing::info!("created EC2 instance at: {public_ip}");
+        Ok(assigned)
+    }
 
-        Ec2Instance::new(
-            public_ip,
+    /// Connects to a launched instance over ssh once it has been assigned its IP addresses.
+    /// Any elastic IP must be associated before `association_deadline`, the same deadline that the addresses were assigned within.
+    async fn connect_instance(
+        &self,
+        definition: &Ec2InstanceDefinition,
+        instance_id: String,
+        launch: Duration,
+        addresses: AssignedAddresses,
+        association_deadline: Instant,
+    ) -> Result<Ec2Instance, Ec2InstanceError> {
+        let AssignedAddresses {
+            mut public_ip,
             private_ip,
-            self.host_public_key_bytes.clone(),
-            &self.client_private_key,
+            private_dns_name,
+            placement,
+            mut network_interfaces,
+            assigned_after,
+        } = addresses;
+        let connect_via_public_ip = self.connect_via_public_ip(definition);
+        let association_start = Instant::now();
+        if let Some(allocation_id) = &definition.elastic_ip_allocation_id {
+            public_ip = Some(
+                self.associate_elastic_ip(&instance_id, allocation_id, association_deadline)
+                    .await?,
+            );
+            // the elastic IP is associated with the primary interface
+            if let Some(primary) = network_interfaces.first_mut() {
+                primary.public_ipv4 = public_ip;
+            }
+        }
+        let ip_assignment = assigned_after + association_start.elapsed();
+        let host_public_key_bytes = if self.replace_host_key {
+            self.host_public_key_bytes.clone()
+        } else {
+            self.host_key_from_console_output(&instance_id).await?
+        };
+        match public_ip {
+            Some(public_ip) => {
+                tracing::info!("created EC2 instance at: {public_ip} (private: {private_ip})")
+            }
+            None => tracing::info!("created EC2 instance at: {private_ip}"),
+        }
+
+        Ec2Instance::new(
+            LaunchedInstance {
+                client: self.client.clone(),
+                audit_log: self.audit_log,
+                instance_id,
+                public_ip,
+                private_ip,
+                private_dns_name,
+                connect_via_public_ip,
+                placement,
+                tags: self.instance_tags(definition),
+                block_devices: definition.block_devices(),
+                network_interfaces,
+                elastic_ip_allocation_id: definition.elastic_ip_allocation_id.clone(),
+                elastic_ip: definition.elastic_ip_allocation_id.as_ref().and(public_ip),
+                creation_timings: Some(CreationTimings {
+                    launch,
+                    ip_assignment,
+                    ssh_connect: Duration::ZERO,
+                }),
+                ssh_connect_timeout: self.ssh_connect_timeout,
+                state_change_timeout: self.state_change_timeout(),
+            },
+            SshCredentials {
+                host_public_key_bytes,
+                client_private_key: definition
+                    .existing_key_pair
+                    .as_ref()
+                    .map(|key_pair| key_pair.private_key.clone())
+                    .unwrap_or_else(|| self.client_private_key.clone()),
+                user: definition.resolved_ssh_user().to_owned(),
+                jump_host: self.jump_host.clone(),
+            },
         )
         .await
     }
 }
 
-enum CpuArch {
-    X86_64,
-    Aarch64,
+/// An instance created by aws-throwaway, returned by [`Aws::list_instances`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceSummary {
+    pub instance_id: String,
+    pub instance_type: Option<InstanceType>,
+    pub state: Option<InstanceStateName>,
+    pub public_ip: Option<IpAddr>,
+    pub private_ip: Option<IpAddr>,
 }
 
-impl CpuArch {
-    fn get_ubuntu_arch_identifier(&self) -> &'static str {
-        match self {
-            CpuArch::X86_64 => "amd64",
-            CpuArch::Aarch64 => "arm64",
-        }
+/// The resources that would be deleted by a cleanup, returned by [`Aws::cleanup_resources_dry_run`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanupPlan {
+    /// The ids of instances that would be terminated
+    pub instances: Vec<String>,
+    /// The ids of security groups that would be deleted
+    pub security_groups: Vec<String>,
+    /// The ids of placement groups that would be deleted
+    pub placement_groups: Vec<String>,
+    /// The ids of key pairs that would be deleted
+    pub key_pairs: Vec<String>,
+}
+
+/// The specifications of an instance type, returned by [`Aws::instance_type_info`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceTypeInfo {
+    /// The default number of vCPUs
+    pub vcpus: u32,
+    /// The amount of memory in MiB
+    pub memory_mib: u64,
+    /// A description of the network performance as given by AWS, e.g. `Up to 12.5 Gigabit`
+    pub network_performance: String,
+    /// The CPU architecture, `None` if AWS reports an architecture unsupported by aws-throwaway
+    pub cpu_arch: Option<CpuArch>,
+    /// The placement strategies the instance type can be launched with, see [`AwsBuilder::placement_strategy`]
+    pub placement_strategies: Vec<PlacementStrategy>,
+}
+
+impl InstanceTypeInfo {
+    /// Returns true if the instance type can be launched with `strategy`
+    pub fn supports_placement_strategy(&self, strategy: &PlacementStrategy) -> bool {
+        self.placement_strategies.contains(strategy)
     }
 }
 
-fn get_arch_of_instance_type(instance_type: InstanceType) -> CpuArch {
-    // Instance names looke like something like:
-    // type + revision_number + subtypes + '.' + size
-    // So say for example `Im4gn.large` would be split into:
-    // type = "Im"
-    // revision_number = 4
-    // subtypes = "gn"
-    // size = "large"
-    //
-    // The 'g' character existing in subtypes indicates that the instance type is a gravitron aka arm instance.
-    // We can check for the existence of 'g' to determine if we are aarch64 or x86_64
-    // This is a bit hacky because this format is not explicitly documented anywhere but the instance type naming does consistently follow this pattern.
-    let mut reached_revision_number = false;
-    for c in instance_type.as_str().chars() {
-        if !reached_revision_number {
-            if c.is_ascii_digit() {
-                reached_revision_number = true;
-            }
-        } else if c == '.' {
-            return CpuArch::X86_64;
-        } else if c == 'g' {
-            return CpuArch::Aarch64;
-        }
+/// Runs `future`, failing with [`Ec2InstanceError::DeadlineExceeded`] naming `phase` if `deadline` is reached first.
+async fn within_deadline<T>(
+    deadline: Option<Instant>,
+    phase: &str,
+    future: impl Future<Output = Result<T, Ec2InstanceError>>,
+) -> Result<T, Ec2InstanceError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| Ec2InstanceError::DeadlineExceeded {
+                phase: phase.to_owned(),
+            })?,
+        None => future.await,
     }
-    unreachable!("Cannot parse instance type: {instance_type:?}")
 }
@@ -0,0 +1,96 @@
+use crate::ec2_instance::Ec2Instance;
+use crate::{Aws, Ec2InstanceDefinition, Ec2InstanceError};
+use std::sync::Mutex;
+
+/// Keeps a number of identical instances warm so that short lived tests do not each pay the boot time of a new instance.
+///
+/// Instances are handed out by [`InstancePool::acquire`] and returned via [`InstancePool::release`],
+/// which cleans them with the command configured by [`InstancePool::reset_command`] so they can be reused.
+pub struct InstancePool<'a> {
+    aws: &'a Aws,
+    definition: Ec2InstanceDefinition,
+    reset_command: Option<String>,
+    available: Mutex<Vec<Ec2Instance>>,
+}
+
+impl<'a> InstancePool<'a> {
+    /// Creates a pool, launching `size` instances of `definition` up front.
+    pub async fn new(
+        aws: &'a Aws,
+        definition: Ec2InstanceDefinition,
+        size: usize,
+    ) -> Result<InstancePool<'a>, Ec2InstanceError> {
+        let instances = aws
+            .create_ec2_instances(vec![definition.clone(); size])
+            .await?;
+        Ok(InstancePool {
+            aws,
+            definition,
+            reset_command: None,
+            available: Mutex::new(instances),
+        })
+    }
+
+    /// A shell command run on each instance when it is released, e.g. `rm -rf ~/test-data && sudo pkill -u ubuntu myservice`,
+    /// to return the instance to a clean state before it is handed out again.
+    /// If the command fails the instance is terminated instead of being returned to the pool.
+    ///
+    /// By default instances are returned to the pool as is.
+    pub fn reset_command(mut self, command: impl Into<String>) -> Self {
+        self.reset_command = Some(command.into());
+        self
+    }
+
+    /// Takes an instance from the pool.
+    /// If the pool is empty a new instance is created, which will join the pool once released.
+    pub async fn acquire(&self) -> Result<Ec2Instance, Ec2InstanceError> {
+        let instance = self.available.lock().unwrap().pop();
+        match instance {
+            Some(instance) => Ok(instance),
+            None => {
+                tracing::info!("instance pool is empty, creating a new instance");
+                self.aws.create_ec2_instance(self.definition.clone()).await
+            }
+        }
+    }
+
+    /// Cleans `instance` with the reset command and returns it to the pool.
+    /// If cleaning fails the instance is terminated and the error returned.
+    pub async fn release(&self, instance: Ec2Instance) -> anyhow::Result<()> {
+        if let Some(command) = &self.reset_command {
+            if let Err(err) = instance.ssh().shell_checked(command).await {
+                tracing::warn!(
+                    "failed to reset instance {}, terminating it: {err:?}",
+                    instance.instance_id()
+                );
+                self.aws
+                    .terminate_instances(vec![instance.instance_id().to_owned()])
+                    .await?;
+                return Err(err);
+            }
+        }
+        self.available.lock().unwrap().push(instance);
+        Ok(())
+    }
+
+    /// The number of instances currently waiting in the pool.
+    pub fn available(&self) -> usize {
+        self.available.lock().unwrap().len()
+    }
+
+    /// Terminates every instance in the pool.
+    /// Instances that are still acquired are not terminated until they are removed by [`Aws::cleanup_resources`].
+    pub async fn shutdown(self) -> Result<(), Ec2InstanceError> {
+        let instance_ids: Vec<String> = self
+            .available
+            .into_inner()
+            .unwrap()
+            .iter()
+            .map(|instance| instance.instance_id().to_owned())
+            .collect();
+        if instance_ids.is_empty() {
+            return Ok(());
+        }
+        self.aws.terminate_instances(instance_ids).await
+    }
+}
@@ -0,0 +1,41 @@
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_client::hyper_ext;
+use hyper::client::HttpConnector;
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_rustls::HttpsConnector;
+
+/// Returns the proxy configured via the `HTTPS_PROXY` environment variable, if any.
+pub fn https_proxy_from_env() -> Option<String> {
+    std::env::var("HTTPS_PROXY")
+        .or_else(|_| std::env::var("https_proxy"))
+        .ok()
+        .filter(|proxy| !proxy.is_empty())
+}
+
+/// Creates a connector for the aws-sdk that tunnels all requests through the proxy at `proxy_url`.
+///
+/// The proxy is only used to CONNECT to the AWS endpoint, TLS is still negotiated end to end with AWS.
+/// Returns an error if `proxy_url` is not a valid URL.
+pub fn connector(proxy_url: &str) -> anyhow::Result<DynConnector> {
+    Ok(DynConnector::new(
+        hyper_ext::Adapter::builder().build(https_connector(proxy_url)?),
+    ))
+}
+
+/// Creates a hyper connector that tunnels all requests through the proxy at `proxy_url`, TLS is negotiated end to end with the destination.
+pub(crate) fn https_connector(
+    proxy_url: &str,
+) -> anyhow::Result<HttpsConnector<ProxyConnector<HttpConnector>>> {
+    let uri = proxy_url
+        .parse::<hyper::Uri>()
+        .map_err(|e| anyhow::anyhow!(e).context(format!("Invalid proxy url {proxy_url:?}")))?;
+    let mut http = HttpConnector::new();
+    // hyper-rustls needs to be able to hand https uris down to the proxy connector
+    http.enforce_http(false);
+    let proxy = ProxyConnector::from_proxy_unsecured(http, Proxy::new(Intercept::All, uri));
+    Ok(hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(proxy))
+}
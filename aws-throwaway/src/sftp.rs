@@ -0,0 +1,575 @@
+//! A minimal SFTP version 3 client, only implementing what is needed to transfer whole files and directory trees.
+//! See https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-02
+
+use anyhow::{anyhow, bail, Result};
+use russh::ChannelStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_LSTAT: u8 = 7;
+const SSH_FXP_SETSTAT: u8 = 9;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_READLINK: u8 = 19;
+const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FXF_READ: u32 = 0x01;
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x02;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x08;
+const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x80000000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// The largest read or write that all servers are required to support
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// The largest packet we accept, matching OpenSSH's sftp-server, so that a malformed length can not make us allocate gigabytes
+const MAX_PACKET_SIZE: u32 = 256 * 1024;
+
+/// The type of a remote file, as far as is needed to copy it
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+pub(crate) struct FileAttributes {
+    pub(crate) kind: FileKind,
+    /// The permission bits of the file, e.g. `0o755`
+    pub(crate) permissions: Option<u32>,
+}
+
+pub(crate) struct DirEntry {
+    pub(crate) name: String,
+    pub(crate) attributes: FileAttributes,
+}
+
+pub(crate) struct SftpSession {
+    stream: ChannelStream,
+    next_id: u32,
+}
+
+impl SftpSession {
+    /// `stream` must be a channel that the sftp subsystem has been requested on.
+    pub(crate) async fn new(stream: ChannelStream) -> Result<Self> {
+        let mut session = SftpSession { stream, next_id: 0 };
+        let mut packet = vec![SSH_FXP_INIT];
+        packet.extend(3u32.to_be_bytes());
+        session.send(&packet).await?;
+        let response = session.receive().await?;
+        if response.first() != Some(&SSH_FXP_VERSION) {
+            bail!("sftp server did not respond with its version");
+        }
+        Ok(session)
+    }
+
+    /// Writes everything from `source` to the file at `path`, creating or truncating it.
+    pub(crate) async fn write_file<R: AsyncRead + Unpin>(
+        &mut self,
+        path: &str,
+        mut source: R,
+    ) -> Result<()> {
+        let handle = self
+            .open(path, SSH_FXF_WRITE | SSH_FXF_CREAT | SSH_FXF_TRUNC)
+            .await?;
+        let mut offset = 0u64;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = source.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            let id = self.id();
+            let mut packet = vec![SSH_FXP_WRITE];
+            packet.extend(id.to_be_bytes());
+            put_string(&mut packet, &handle);
+            packet.extend(offset.to_be_bytes());
+            put_string(&mut packet, &buffer[..read]);
+            self.send(&packet).await?;
+            self.expect_ok(id).await?;
+            offset += read as u64;
+        }
+        self.close(handle).await
+    }
+
+    /// Reads the entire file at `path` into `dest`.
+    pub(crate) async fn read_file<W: AsyncWrite + Unpin>(
+        &mut self,
+        path: &str,
+        mut dest: W,
+    ) -> Result<()> {
+        let handle = self.open(path, SSH_FXF_READ).await?;
+        let mut offset = 0u64;
+        loop {
+            let id = self.id();
+            let mut packet = vec![SSH_FXP_READ];
+            packet.extend(id.to_be_bytes());
+            put_string(&mut packet, &handle);
+            packet.extend(offset.to_be_bytes());
+            packet.extend((CHUNK_SIZE as u32).to_be_bytes());
+            self.send(&packet).await?;
+
+            let response = self.receive_response(id).await?;
+            match response.first() {
+                Some(&SSH_FXP_DATA) => {
+                    let data = get_string(&response[5..])?;
+                    dest.write_all(data).await?;
+                    offset += data.len() as u64;
+                }
+                Some(&SSH_FXP_STATUS) if status_code(&response)? == SSH_FX_EOF => break,
+                _ => return Err(status_error(&response)),
+            }
+        }
+        dest.flush().await?;
+        self.close(handle).await
+    }
+
+    /// Returns the attributes of the file at `path` without following symlinks, or `None` if it does not exist.
+    pub(crate) async fn lstat(&mut self, path: &str) -> Result<Option<FileAttributes>> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_LSTAT];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        self.send(&packet).await?;
+
+        let response = self.receive_response(id).await?;
+        match response.first() {
+            Some(&SSH_FXP_ATTRS) => Ok(Some(Reader::new(&response[5..]).attributes()?)),
+            Some(&SSH_FXP_STATUS) if status_code(&response)? == SSH_FX_NO_SUCH_FILE => Ok(None),
+            _ => Err(status_error(&response)),
+        }
+    }
+
+    /// Lists the entries of the directory at `path`, excluding `.` and `..`.
+    pub(crate) async fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_OPENDIR];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        self.send(&packet).await?;
+        let response = self.receive_response(id).await?;
+        if response.first() != Some(&SSH_FXP_HANDLE) {
+            return Err(status_error(&response).context(format!("Failed to open {path:?}")));
+        }
+        let handle = get_string(&response[5..])?.to_vec();
+
+        let mut entries = vec![];
+        loop {
+            let id = self.id();
+            let mut packet = vec![SSH_FXP_READDIR];
+            packet.extend(id.to_be_bytes());
+            put_string(&mut packet, &handle);
+            self.send(&packet).await?;
+
+            let response = self.receive_response(id).await?;
+            match response.first() {
+                Some(&SSH_FXP_NAME) => {
+                    let mut reader = Reader::new(&response[5..]);
+                    for _ in 0..reader.u32()? {
+                        let name = String::from_utf8_lossy(reader.string()?).into_owned();
+                        // the `ls -l` style long name is not needed
+                        reader.string()?;
+                        let attributes = reader.attributes()?;
+                        if name != "." && name != ".." {
+                            entries.push(DirEntry { name, attributes });
+                        }
+                    }
+                }
+                Some(&SSH_FXP_STATUS) if status_code(&response)? == SSH_FX_EOF => break,
+                _ => return Err(status_error(&response)),
+            }
+        }
+        self.close(handle).await?;
+        Ok(entries)
+    }
+
+    pub(crate) async fn mkdir(&mut self, path: &str, permissions: Option<u32>) -> Result<()> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_MKDIR];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        put_permissions(&mut packet, permissions);
+        self.send(&packet).await?;
+        self.expect_ok(id)
+            .await
+            .map_err(|e| e.context(format!("Failed to create directory {path:?}")))
+    }
+
+    pub(crate) async fn set_permissions(&mut self, path: &str, permissions: u32) -> Result<()> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_SETSTAT];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        put_permissions(&mut packet, Some(permissions));
+        self.send(&packet).await?;
+        self.expect_ok(id)
+            .await
+            .map_err(|e| e.context(format!("Failed to set permissions of {path:?}")))
+    }
+
+    /// Removes the file or symlink at `path`.
+    pub(crate) async fn remove(&mut self, path: &str) -> Result<()> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_REMOVE];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        self.send(&packet).await?;
+        self.expect_ok(id)
+            .await
+            .map_err(|e| e.context(format!("Failed to remove {path:?}")))
+    }
+
+    pub(crate) async fn read_link(&mut self, path: &str) -> Result<String> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_READLINK];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        self.send(&packet).await?;
+
+        let response = self.receive_response(id).await?;
+        if response.first() == Some(&SSH_FXP_NAME) {
+            let mut reader = Reader::new(&response[5..]);
+            if reader.u32()? == 1 {
+                return Ok(String::from_utf8_lossy(reader.string()?).into_owned());
+            }
+        }
+        Err(status_error(&response).context(format!("Failed to read symlink {path:?}")))
+    }
+
+    /// Creates a symlink at `link` pointing to `target`.
+    pub(crate) async fn symlink(&mut self, target: &str, link: &str) -> Result<()> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_SYMLINK];
+        packet.extend(id.to_be_bytes());
+        // OpenSSH's sftp-server takes the arguments in the reverse order to the specification,
+        // every other server in common use has followed OpenSSH.
+        put_string(&mut packet, target.as_bytes());
+        put_string(&mut packet, link.as_bytes());
+        self.send(&packet).await?;
+        self.expect_ok(id)
+            .await
+            .map_err(|e| e.context(format!("Failed to create symlink {link:?}")))
+    }
+
+    async fn open(&mut self, path: &str, flags: u32) -> Result<Vec<u8>> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_OPEN];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, path.as_bytes());
+        packet.extend(flags.to_be_bytes());
+        // no attributes
+        packet.extend(0u32.to_be_bytes());
+        self.send(&packet).await?;
+
+        let response = self.receive_response(id).await?;
+        if response.first() == Some(&SSH_FXP_HANDLE) {
+            Ok(get_string(&response[5..])?.to_vec())
+        } else {
+            Err(status_error(&response).context(format!("Failed to open {path:?}")))
+        }
+    }
+
+    async fn close(&mut self, handle: Vec<u8>) -> Result<()> {
+        let id = self.id();
+        let mut packet = vec![SSH_FXP_CLOSE];
+        packet.extend(id.to_be_bytes());
+        put_string(&mut packet, &handle);
+        self.send(&packet).await?;
+        self.expect_ok(id).await
+    }
+
+    async fn expect_ok(&mut self, id: u32) -> Result<()> {
+        let response = self.receive_response(id).await?;
+        if response.first() == Some(&SSH_FXP_STATUS) && status_code(&response)? == SSH_FX_OK {
+            Ok(())
+        } else {
+            Err(status_error(&response))
+        }
+    }
+
+    fn id(&mut self) -> u32 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.next_id
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.stream
+            .write_all(&(packet.len() as u32).to_be_bytes())
+            .await?;
+        self.stream.write_all(packet).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Vec<u8>> {
+        let len = self.stream.read_u32().await?;
+        if len > MAX_PACKET_SIZE {
+            bail!("sftp server sent a {len} byte packet, larger than the maximum of {MAX_PACKET_SIZE}");
+        }
+        let mut packet = vec![0; len as usize];
+        self.stream.read_exact(&mut packet).await?;
+        Ok(packet)
+    }
+
+    /// Receives the response to request `id`, since requests are sent one at a time there are never any other responses in flight.
+    async fn receive_response(&mut self, id: u32) -> Result<Vec<u8>> {
+        let response = self.receive().await?;
+        if response.len() < 5 || response[1..5] != id.to_be_bytes() {
+            bail!("sftp server sent a response for an unexpected request");
+        }
+        Ok(response)
+    }
+}
+
+fn put_string(packet: &mut Vec<u8>, value: &[u8]) {
+    packet.extend((value.len() as u32).to_be_bytes());
+    packet.extend(value);
+}
+
+fn put_permissions(packet: &mut Vec<u8>, permissions: Option<u32>) {
+    match permissions {
+        Some(permissions) => {
+            packet.extend(SSH_FILEXFER_ATTR_PERMISSIONS.to_be_bytes());
+            packet.extend(permissions.to_be_bytes());
+        }
+        None => packet.extend(0u32.to_be_bytes()),
+    }
+}
+
+/// Parses the fields of a packet in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("sftp packet was truncated");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn attributes(&mut self) -> Result<FileAttributes> {
+        let flags = self.u32()?;
+        if flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+            self.take(8)?;
+        }
+        if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        let mode = if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+            Some(self.u32()?)
+        } else {
+            None
+        };
+        if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+            self.take(8)?;
+        }
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+        let kind = match mode.map(|mode| mode & S_IFMT) {
+            Some(S_IFDIR) => FileKind::Directory,
+            Some(S_IFLNK) => FileKind::Symlink,
+            _ => FileKind::File,
+        };
+        Ok(FileAttributes {
+            kind,
+            permissions: mode.map(|mode| mode & 0o7777),
+        })
+    }
+}
+
+fn get_string(bytes: &[u8]) -> Result<&[u8]> {
+    let len = bytes
+        .get(..4)
+        .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+        .ok_or_else(|| anyhow!("sftp packet was truncated"))?;
+    bytes
+        .get(4..)
+        .and_then(|rest| rest.get(..len))
+        .ok_or_else(|| anyhow!("sftp packet was truncated"))
+}
+
+fn status_code(response: &[u8]) -> Result<u32> {
+    response
+        .get(5..9)
+        .map(|code| u32::from_be_bytes(code.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("sftp status packet was truncated"))
+}
+
+fn status_error(response: &[u8]) -> anyhow::Error {
+    if response.first() != Some(&SSH_FXP_STATUS) {
+        return anyhow!(
+            "sftp server sent unexpected packet type {:?}",
+            response.first()
+        );
+    }
+    let message = response
+        .get(9..)
+        .and_then(|x| get_string(x).ok())
+        .map(|x| String::from_utf8_lossy(x).into_owned())
+        .unwrap_or_default();
+    match status_code(response) {
+        Ok(code) => anyhow!("sftp request failed with status {code}: {message}"),
+        Err(err) => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &[u8]) -> Vec<u8> {
+        let mut packet = vec![];
+        put_string(&mut packet, value);
+        packet
+    }
+
+    fn status(code: u32, message: &str) -> Vec<u8> {
+        let mut packet = vec![SSH_FXP_STATUS];
+        packet.extend(7u32.to_be_bytes());
+        packet.extend(code.to_be_bytes());
+        put_string(&mut packet, message.as_bytes());
+        put_string(&mut packet, b"en");
+        packet
+    }
+
+    #[test]
+    fn parse_string() {
+        assert_eq!(get_string(&string(b"handle")).unwrap(), b"handle");
+        assert_eq!(get_string(&string(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn parse_truncated_string() {
+        let packet = string(b"handle");
+        assert!(get_string(&packet[..packet.len() - 1]).is_err());
+        assert!(get_string(&packet[..2]).is_err());
+        assert!(get_string(&[]).is_err());
+        assert!(get_string(&u32::MAX.to_be_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_attributes() {
+        let mut packet = vec![];
+        packet.extend(
+            (SSH_FILEXFER_ATTR_SIZE | SSH_FILEXFER_ATTR_PERMISSIONS | SSH_FILEXFER_ATTR_EXTENDED)
+                .to_be_bytes(),
+        );
+        packet.extend(1024u64.to_be_bytes());
+        packet.extend((S_IFDIR | 0o755).to_be_bytes());
+        packet.extend(1u32.to_be_bytes());
+        packet.extend(string(b"name"));
+        packet.extend(string(b"value"));
+
+        let attributes = Reader::new(&packet).attributes().unwrap();
+        assert!(attributes.kind == FileKind::Directory);
+        assert_eq!(attributes.permissions, Some(0o755));
+
+        let attributes = Reader::new(&0u32.to_be_bytes()).attributes().unwrap();
+        assert!(attributes.kind == FileKind::File);
+        assert_eq!(attributes.permissions, None);
+    }
+
+    #[test]
+    fn parse_truncated_attributes() {
+        let mut packet = vec![];
+        packet.extend((SSH_FILEXFER_ATTR_SIZE | SSH_FILEXFER_ATTR_PERMISSIONS).to_be_bytes());
+        packet.extend(1024u64.to_be_bytes());
+        packet.extend((S_IFLNK | 0o777).to_be_bytes());
+        for len in 0..packet.len() {
+            assert!(Reader::new(&packet[..len]).attributes().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_malformed_attributes() {
+        // claims a huge number of extended attributes that are not present
+        let mut packet = vec![];
+        packet.extend(SSH_FILEXFER_ATTR_EXTENDED.to_be_bytes());
+        packet.extend(u32::MAX.to_be_bytes());
+        assert!(Reader::new(&packet).attributes().is_err());
+    }
+
+    #[test]
+    fn parse_status() {
+        let packet = status(SSH_FX_NO_SUCH_FILE, "No such file");
+        assert_eq!(status_code(&packet).unwrap(), SSH_FX_NO_SUCH_FILE);
+        assert_eq!(
+            status_error(&packet).to_string(),
+            "sftp request failed with status 2: No such file"
+        );
+    }
+
+    #[test]
+    fn parse_truncated_status() {
+        let packet = status(SSH_FX_NO_SUCH_FILE, "No such file");
+        assert!(status_code(&packet[..8]).is_err());
+        assert!(status_code(&[]).is_err());
+        assert_eq!(
+            status_error(&packet[..8]).to_string(),
+            "sftp status packet was truncated"
+        );
+        // a truncated message is dropped rather than failing
+        assert_eq!(
+            status_error(&packet[..12]).to_string(),
+            "sftp request failed with status 2: "
+        );
+    }
+
+    #[test]
+    fn parse_unexpected_packet() {
+        assert_eq!(
+            status_error(&[SSH_FXP_HANDLE, 0, 0, 0, 1]).to_string(),
+            format!("sftp server sent unexpected packet type Some({SSH_FXP_HANDLE})")
+        );
+        assert_eq!(
+            status_error(&[]).to_string(),
+            "sftp server sent unexpected packet type None"
+        );
+    }
+}
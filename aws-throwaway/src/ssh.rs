@@ -1,97 +1,350 @@
-use anyhow::{anyhow, Result};
+use crate::sftp::{FileKind, SftpSession};
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
+use futures::FutureExt;
 use russh::{
-    client::{Config, Handle, Handler},
-    ChannelMsg, Sig,
+    client::{Config, Handle, Handler, Msg},
+    Channel, ChannelMsg, Sig,
 };
 use russh_keys::{key::PublicKey, PublicKeyBase64};
-use std::{fmt::Display, io::Write, net::IpAddr, path::Path, sync::Arc};
+use std::{
+    fmt::Display,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, BufReader},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+    time::Instant,
 };
+use uuid::Uuid;
 
+/// A single ssh connection to an instance that is kept open for the lifetime of the instance's [`Ec2Instance`](crate::ec2_instance::Ec2Instance).
+///
+/// Each command is run on its own channel multiplexed over the one connection,
+/// so running a command only costs a round trip to open the channel rather than a new TCP connection, key exchange and authentication.
 pub struct SshConnection {
     address: IpAddr,
-    session: Handle<Client>,
+    session: Arc<Handle<Client>>,
+    /// The connection to the jump host that this connection is tunneled through, kept alive for as long as this connection
+    _jump_host: Option<Arc<SshConnection>>,
+}
+
+/// A bastion host that ssh connections to instances are tunneled through, like ssh's `ProxyJump`, configured via
+/// [`AwsBuilder::ssh_jump_host`](crate::AwsBuilder::ssh_jump_host).
+#[derive(Clone)]
+pub struct JumpHost {
+    address: String,
+    port: u16,
+    user: String,
+    private_key: String,
+    host_public_key: String,
+}
+
+// Implemented manually so that the private key does not end up in logs.
+impl std::fmt::Debug for JumpHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JumpHost")
+            .field("address", &self.address)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("private_key", &"<redacted>")
+            .field("host_public_key", &self.host_public_key)
+            .finish()
+    }
+}
+
+impl JumpHost {
+    /// `address` is the hostname or IP of the jump host, which is logged into as `user` with the OpenSSH formatted `private_key`.
+    /// The jump host is authenticated against `host_public_key`, its OpenSSH formatted public host key e.g. `ssh-ed25519 AAAA...`,
+    /// as found in `/etc/ssh/ssh_host_ed25519_key.pub` on the jump host.
+    pub fn new(
+        address: impl Into<String>,
+        user: impl Into<String>,
+        private_key: impl Into<String>,
+        host_public_key: impl Into<String>,
+    ) -> Self {
+        JumpHost {
+            address: address.into(),
+            port: 22,
+            user: user.into(),
+            private_key: private_key.into(),
+            host_public_key: host_public_key.into(),
+        }
+    }
+
+    /// The port the jump host's ssh server listens on.
+    /// Defaults to 22.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Formats the jump host for ssh's `-J` flag
+    pub(crate) fn proxy_jump(&self) -> String {
+        format!("{}@{}:{}", self.user, self.address, self.port)
+    }
+
+    async fn connect(&self) -> Result<SshConnection> {
+        let host_public_key_bytes = ssh_key::PublicKey::from_openssh(&self.host_public_key)
+            .and_then(|key| key.to_bytes())
+            .map_err(|e| anyhow!(e).context("Failed to decode the jump host's public key"))?;
+        let stream = TcpStream::connect((self.address.as_str(), self.port))
+            .await
+            .map_err(|e| {
+                anyhow!(e).context(format!(
+                    "Failed to connect to jump host {}:{}",
+                    self.address, self.port
+                ))
+            })?;
+        let address = stream.peer_addr()?.ip();
+        let credentials = SshCredentials {
+            host_public_key_bytes,
+            client_private_key: self.private_key.clone(),
+            user: self.user.clone(),
+            jump_host: None,
+        };
+        match SshConnection::new(Box::new(stream), address, &credentials, None).await {
+            Ok(connection) => Ok(connection),
+            Err(SshConnectError::Fatal(err) | SshConnectError::Other(err)) => {
+                Err(err.context(format!("Failed to connect to jump host {}", self.address)))
+            }
+            Err(SshConnectError::AuthRejected) => bail!(
+                "The jump host {} rejected authentication as {}",
+                self.address,
+                self.user
+            ),
+        }
+    }
+}
+
+/// A stream that an ssh connection can be established over
+pub(crate) trait SshStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> SshStream for T {}
+
+/// Everything needed to authenticate an ssh connection with an instance, in both directions.
+/// The instance is identified purely by its host key, so the credentials remain valid if the instance's IP changes.
+#[derive(Clone)]
+pub(crate) struct SshCredentials {
+    pub host_public_key_bytes: Vec<u8>,
+    pub client_private_key: String,
+    pub user: String,
+    pub jump_host: Option<Arc<JumpHost>>,
+}
+
+/// Why [`SshConnection::new`] failed, so that the caller can decide whether retrying could help
+pub(crate) enum SshConnectError {
+    /// Retrying will never succeed, e.g. the private key could not be parsed.
+    Fatal(anyhow::Error),
+    /// The server rejected our key, this is expected for a short time while the instance is booting.
+    AuthRejected,
+    /// The connection failed before authentication completed, e.g. because the host key has not yet been replaced by the user-data script.
+    Other(anyhow::Error),
 }
 
 impl SshConnection {
-    pub async fn new(
-        stream: TcpStream,
+    pub(crate) async fn new(
+        stream: Box<dyn SshStream>,
         address: IpAddr,
-        host_public_key_bytes: Vec<u8>,
-        client_private_key: &str,
-    ) -> Result<Self> {
+        credentials: &SshCredentials,
+        jump_host: Option<Arc<SshConnection>>,
+    ) -> std::result::Result<Self, SshConnectError> {
         let config = Arc::new(Config::default());
 
         let key = Arc::new(
-            russh_keys::decode_secret_key(client_private_key, None)
-                .map_err(|e| anyhow!(e).context("Failed to connect to ssh server"))?,
+            russh_keys::decode_secret_key(&credentials.client_private_key, None).map_err(|e| {
+                SshConnectError::Fatal(
+                    anyhow!(e).context("Failed to decode the client private key"),
+                )
+            })?,
         );
         let mut session = russh::client::connect_stream(
             config,
             stream,
             Client {
-                host_public_key_bytes,
+                host_public_key_bytes: credentials.host_public_key_bytes.clone(),
             },
         )
-        .await?;
-        if session.authenticate_publickey("ubuntu", key).await.unwrap() {
+        .await
+        .map_err(SshConnectError::Other)?;
+        if session
+            .authenticate_publickey(&credentials.user, key)
+            .await
+            .map_err(|e| SshConnectError::Other(e.into()))?
+        {
             tracing::info!("Succesfully connected to {address} over ssh");
-            Ok(SshConnection { session, address })
+            Ok(SshConnection {
+                session: Arc::new(session),
+                address,
+                _jump_host: jump_host,
+            })
         } else {
-            Err(anyhow!("Authentication with ssh server failed"))
+            Err(SshConnectError::AuthRejected)
         }
     }
 
+    /// Connects to the jump host of `credentials` if there is one
+    pub(crate) async fn connect_jump_host(
+        credentials: &SshCredentials,
+    ) -> Result<Option<Arc<SshConnection>>> {
+        match &credentials.jump_host {
+            Some(jump_host) => Ok(Some(Arc::new(jump_host.connect().await?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens a stream to port 22 of `address` tunneled through this connection, for connecting to an instance via a jump host
+    pub(crate) async fn tunnel_ssh(&self, address: IpAddr) -> Result<Box<dyn SshStream>> {
+        let channel = self
+            .session
+            .channel_open_direct_tcpip(address.to_string(), 22, "127.0.0.1", 0)
+            .await
+            .map_err(|e| {
+                anyhow!(e).context(format!(
+                    "Failed to tunnel to {address}:22 through jump host {}",
+                    self.address
+                ))
+            })?;
+        Ok(Box::new(channel.into_stream()))
+    }
+
+    /// Returns true if the instance can open a TCP connection to `host:port`, as tested by tunneling a connection through ssh.
+    pub(crate) async fn port_is_open(&self, host: &str, port: u16) -> bool {
+        match self
+            .session
+            .channel_open_direct_tcpip(host, port as u32, "127.0.0.1", 0)
+            .await
+        {
+            Ok(channel) => {
+                channel.close().await.ok();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns false if the connection has been lost, e.g. because the instance rebooted.
+    pub(crate) async fn is_alive(&self) -> bool {
+        if self.session.is_closed() {
+            return false;
+        }
+        match tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            self.session.channel_open_session(),
+        )
+        .await
+        {
+            Ok(Ok(channel)) => {
+                channel.close().await.ok();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Run a command and return its output once it has finished.
+    /// A non-zero exit status is not treated as an error, check [`CommandOutput::exit_status`] or use [`SshConnection::shell_checked`] instead.
     pub async fn shell(&self, command: &str) -> CommandOutput {
         tracing::info!("running command on {}: {}", self.address, command);
 
         let mut channel = self.session.channel_open_session().await.unwrap();
         channel.exec(true, command).await.unwrap();
-        let mut stdout = vec![];
-        let mut stderr = vec![];
-        let mut status = None;
-        let mut failed = None;
-        while let Some(msg) = channel.wait().await {
-            match msg {
-                ChannelMsg::Data { data } => stdout.write_all(&data).unwrap(),
-                ChannelMsg::ExtendedData { data, ext } => {
-                    if ext == 1 {
-                        stderr.write_all(&data).unwrap()
-                    } else {
-                        tracing::warn!("received unknown extended data with extension type {ext} containing: {:?}", data.to_vec())
-                    }
-                }
-                ChannelMsg::ExitStatus { exit_status } => {
-                    status = Some(exit_status);
-                    // cant exit immediately, there might be more data still
-                }
-                ChannelMsg::ExitSignal {
-                    signal_name,
-                    core_dumped,
-                    error_message,
-                    ..
-                } => {
-                    failed = Some(format!(
-                    "killed via signal {signal_name:?} core_dumped={core_dumped} {error_message:?}"
-                ))
-                }
-                _ => {}
-            }
+        let mut output = ReceivedOutput::default();
+        read_output(&mut channel, command, &mut output).await;
+        output.into_command_output().unwrap()
+    }
+
+    /// Like [`SshConnection::shell`] but allocates a pseudo-terminal for the command,
+    /// for tools that only output colors and progress bars or refuse to run at all without a TTY.
+    ///
+    /// A terminal has a single output stream so stdout and stderr are merged into [`CommandOutput::stdout`] and lines end in `\r\n`.
+    pub async fn shell_tty(&self, command: &str) -> CommandOutput {
+        tracing::info!("running command with tty on {}: {}", self.address, command);
+
+        let mut channel = self.session.channel_open_session().await.unwrap();
+        channel
+            .request_pty(true, "xterm-256color", 200, 50, 0, 0, &[])
+            .await
+            .unwrap();
+        channel.exec(true, command).await.unwrap();
+        let mut output = ReceivedOutput::default();
+        read_output(&mut channel, command, &mut output).await;
+        output.into_command_output().unwrap()
+    }
+
+    /// Like [`SshConnection::shell`] but gives up on the command if it has not finished within `timeout`.
+    /// When the timeout is exceeded the command is sent SIGKILL, the channel is closed and an error containing any output received so far is returned.
+    ///
+    /// The exit status is not checked, a command that fails within the timeout returns `Ok`.
+    pub async fn shell_with_timeout(
+        &self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<CommandOutput> {
+        tracing::info!(
+            "running command on {} with timeout {timeout:?}: {}",
+            self.address,
+            command
+        );
+        let deadline = Instant::now() + timeout;
+
+        let mut channel = self.session.channel_open_session().await?;
+        channel.exec(true, command).await?;
+        let mut output = ReceivedOutput::default();
+        if tokio::time::timeout_at(deadline, read_output(&mut channel, command, &mut output))
+            .await
+            .is_err()
+        {
+            // Not every ssh server supports signals, closing the channel will at least hang up on the command.
+            channel.signal(Sig::KILL).await.ok();
+            channel.close().await.ok();
+            let output = output.into_command_output_lossy();
+            return Err(anyhow!(
+                "The command {command} did not finish within {timeout:?}\n{output}"
+            ));
         }
-        let output = CommandOutput {
-            stdout: String::from_utf8(stdout).unwrap(),
-            stderr: String::from_utf8(stderr).unwrap(),
-        };
+        output.into_command_output()
+    }
 
-        check_results(&format!("The command {command}"), failed, status, &output);
-        output
+    /// Run a command and return its output once it has finished.
+    /// Returns an error if the command does not exit with a status of 0.
+    pub async fn shell_checked(&self, command: &str) -> Result<CommandOutput> {
+        let output = self.shell(command).await;
+        match output.exit_status {
+            Some(0) => Ok(output),
+            Some(status) => Err(anyhow!(
+                "The command {command} failed with exit code {status}\n{output}"
+            )),
+            None => Err(anyhow!(
+                "The command {command} did not exit cleanly\n{output}"
+            )),
+        }
+    }
+
+    /// Like [`SshConnection::shell`] but exports each `(name, value)` pair in `env` as an environment variable before running the command.
+    /// Values are escaped so they may safely contain spaces, quotes or any other special characters.
+    ///
+    /// Panics if a name is not a valid environment variable name, i.e. it does not match `[A-Za-z_][A-Za-z0-9_]*`.
+    pub async fn shell_with_env(&self, env: &[(&str, &str)], command: &str) -> CommandOutput {
+        self.shell(&env_script(env, command)).await
+    }
+
+    /// Like [`SshConnection::shell`] but runs the command as root via `sudo bash -c`.
+    /// The command is escaped so it may contain quotes, pipes, redirects and environment variables, which are all evaluated as root.
+    /// Requires the user to have passwordless sudo, as is the case for the default user of every [`InstanceOs`](crate::InstanceOs).
+    pub async fn sudo(&self, command: &str) -> CommandOutput {
+        self.shell(&format!("sudo -n bash -c {}", shell_escape(command)))
+            .await
     }
 
     // Run a service and return its logs over stdout
+    // The output is read by a task spawned onto the tokio runtime this is called from.
     pub async fn shell_stdout_lines(
         &self,
         command: &str,
@@ -166,15 +419,176 @@ impl SshConnection {
         rx
     }
 
+    /// Run a command and stream its stdout and stderr lines back as they are produced.
+    /// The final event is always [`ShellEvent::Exited`] unless the receiver is dropped first, which also stops reading from the command.
+    /// The output is read by a task spawned onto the tokio runtime this is called from.
+    pub async fn shell_stream(
+        &self,
+        command: &str,
+    ) -> tokio::sync::mpsc::UnboundedReceiver<ShellEvent> {
+        tracing::info!("running shell_stream on {}: {}", self.address, command);
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut channel = self.session.channel_open_session().await.unwrap();
+        channel.exec(true, command).await.unwrap();
+        tokio::task::spawn(async move {
+            let mut stdout = vec![];
+            let mut stderr = vec![];
+            let mut status = None;
+            loop {
+                tokio::select! {
+                    msg = channel.wait() => {
+                        match msg {
+                            Some(ChannelMsg::Data { data }) => {
+                                stdout.write_all(&data).unwrap();
+                                if !send_lines(&mut stdout, &tx, ShellEvent::Stdout) {
+                                    return;
+                                }
+                            }
+                            Some(ChannelMsg::ExtendedData { data, ext }) => {
+                                if ext == 1 {
+                                    stderr.write_all(&data).unwrap();
+                                    if !send_lines(&mut stderr, &tx, ShellEvent::Stderr) {
+                                        return;
+                                    }
+                                } else {
+                                    tracing::warn!("received unknown extended data with extension type {ext} containing: {:?}", data.to_vec())
+                                }
+                            }
+                            Some(ChannelMsg::ExitStatus { exit_status }) => {
+                                status = Some(exit_status);
+                                // cant exit immediately, there might be more data still
+                            }
+                            Some(_) => {}
+                            None => {
+                                // flush any trailing output that did not end in a newline
+                                if !stdout.is_empty() {
+                                    tx.send(ShellEvent::Stdout(String::from_utf8(stdout).unwrap())).ok();
+                                }
+                                if !stderr.is_empty() {
+                                    tx.send(ShellEvent::Stderr(String::from_utf8(stderr).unwrap())).ok();
+                                }
+                                tx.send(ShellEvent::Exited(status)).ok();
+                                return;
+                            }
+                        }
+                    },
+                    _ = tx.closed() => {
+                        return;
+                    },
+                };
+            }
+        });
+
+        rx
+    }
+
+    /// Pushes the script at `local_path` to a temporary file on the instance, runs it and then removes it.
+    /// The script must be executable by itself, e.g. by starting with a `#!/bin/bash` shebang,
+    /// but the local file does not need to have its executable bit set.
+    pub async fn run_script(&self, local_path: &Path) -> CommandOutput {
+        let remote_path = format!("/tmp/aws-throwaway-script-{}", Uuid::new_v4());
+        self.push_file_with_mode(local_path, Path::new(&remote_path), 0o700)
+            .await;
+        let output = self.shell(&format!("'{remote_path}'")).await;
+        self.shell(&format!("rm -f '{remote_path}'")).await;
+        output
+    }
+
+    /// Listens on `local_port` on the local machine and forwards every connection through the ssh connection to `remote_port` on the instance.
+    /// Pass a `local_port` of 0 to have a free port picked, see [`PortForward::local_addr`].
+    ///
+    /// The tunnel stays open until the returned [`PortForward`] is dropped.
+    /// Connections are handled by tasks spawned onto the tokio runtime this is called from.
+    pub async fn forward_local_port(
+        &self,
+        local_port: u16,
+        remote_port: u16,
+    ) -> Result<PortForward> {
+        let listener = TcpListener::bind(("127.0.0.1", local_port))
+            .await
+            .map_err(|e| {
+                anyhow!(e).context(format!("Failed to listen on local port {local_port}"))
+            })?;
+        let local_addr = listener.local_addr()?;
+        tracing::info!(
+            "forwarding {local_addr} to port {remote_port} on {}",
+            self.address
+        );
+
+        let session = self.session.clone();
+        let address = self.address;
+        let task = tokio::spawn(async move {
+            // dropping the JoinSet when this task is aborted also aborts every connection
+            let mut connections = JoinSet::new();
+            loop {
+                let (mut stream, originator) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("Failed to accept connection on {local_addr}: {err}");
+                        continue;
+                    }
+                };
+                let session = session.clone();
+                connections.spawn(async move {
+                    let channel = match session
+                        .channel_open_direct_tcpip(
+                            "127.0.0.1",
+                            remote_port as u32,
+                            originator.ip().to_string(),
+                            originator.port() as u32,
+                        )
+                        .await
+                    {
+                        Ok(channel) => channel,
+                        Err(err) => {
+                            tracing::warn!(
+                                "Failed to open tunnel to port {remote_port} on {address}: {err}"
+                            );
+                            return;
+                        }
+                    };
+                    let mut channel = channel.into_stream();
+                    if let Err(err) = tokio::io::copy_bidirectional(&mut stream, &mut channel).await
+                    {
+                        tracing::info!(
+                            "tunnel to port {remote_port} on {address} was closed: {err}"
+                        );
+                    }
+                });
+                // reap finished connections so they dont accumulate
+                while connections.join_next().now_or_never().flatten().is_some() {}
+            }
+        });
+        Ok(PortForward { local_addr, task })
+    }
+
+    /// Copies the local file `source` to `dest` on the instance.
+    /// On unix the permission bits of `source` are preserved, elsewhere the remote file is made readable, writable and executable by everyone.
     pub async fn push_file(&self, source: &Path, dest: &Path) {
-        let task = format!("pushing file from {source:?} to {}:{dest:?}", self.address);
+        let metadata = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to read from {source:?}")))
+            .unwrap();
+        let mode = local_permissions(&metadata).unwrap_or(0o777);
+        self.push_file_with_mode(source, dest, mode).await;
+    }
+
+    /// Like [`SshConnection::push_file`] but sets the permission bits of the remote file to `mode`,
+    /// e.g. `0o755` for an executable script or `0o600` for a secret.
+    pub async fn push_file_with_mode(&self, source: &Path, dest: &Path, mode: u32) {
+        let task = format!(
+            "pushing file from {source:?} to {}:{dest:?} with mode {mode:o}",
+            self.address
+        );
         tracing::info!("{task}");
 
         let source = File::open(source)
             .await
             .map_err(|e| anyhow!(e).context(format!("Failed to read from {source:?}")))
             .unwrap();
-        self.push_file_impl(&task, source, dest).await;
+        self.push_file_impl_with_mode(&task, source, dest, mode)
+            .await;
     }
 
     pub async fn push_file_from_bytes(&self, bytes: &[u8], dest: &Path) {
@@ -191,16 +605,58 @@ impl SshConnection {
         source: R,
         dest: &Path,
     ) {
-        let mut channel = self.session.channel_open_session().await.unwrap();
-        let command = format!("dd of='{0}'\nchmod 777 {0}", dest.to_str().unwrap());
-        channel.exec(true, command).await.unwrap();
+        self.push_file_impl_with_mode(task, source, dest, 0o777)
+            .await
+    }
+
+    async fn push_file_impl_with_mode<R: AsyncReadExt + Unpin>(
+        &self,
+        task: &str,
+        source: R,
+        dest: &Path,
+        mode: u32,
+    ) {
+        if let Err(err) = self
+            .try_push_file_impl_with_mode(task, source, dest, mode)
+            .await
+        {
+            panic!("{err:?}")
+        }
+    }
+
+    /// Like [`SshConnection::push_file_with_mode`] but pushes `bytes` and returns an error instead of panicking.
+    pub(crate) async fn try_push_bytes_with_mode(
+        &self,
+        bytes: &[u8],
+        dest: &Path,
+        mode: u32,
+    ) -> Result<()> {
+        let task = format!(
+            "pushing raw bytes to {}:{dest:?} with mode {mode:o}",
+            self.address
+        );
+        tracing::info!("{task}");
+        self.try_push_file_impl_with_mode(&task, BufReader::new(bytes), dest, mode)
+            .await
+    }
+
+    async fn try_push_file_impl_with_mode<R: AsyncReadExt + Unpin>(
+        &self,
+        task: &str,
+        source: R,
+        dest: &Path,
+        mode: u32,
+    ) -> Result<()> {
+        let mut channel = self.session.channel_open_session().await?;
+        let command = format!("dd of='{0}'\nchmod {mode:o} '{0}'", path_to_str(dest)?);
+        channel.exec(true, command).await?;
 
         let mut stdout = vec![];
         let mut stderr = vec![];
         let mut status = None;
         let mut failed = None;
-        channel.data(source).await.unwrap();
-        channel.eof().await.unwrap();
+        channel.data(source).await?;
+        channel.eof().await?;
         while let Some(msg) = channel.wait().await {
             match msg {
                 ChannelMsg::Data { data } => stdout.write_all(&data).unwrap(),
@@ -231,9 +687,190 @@ impl SshConnection {
         let output = CommandOutput {
             stdout: String::from_utf8(stdout).unwrap(),
             stderr: String::from_utf8(stderr).unwrap(),
+            exit_status: status,
         };
 
-        check_results(task, failed, status, &output);
+        command_result(task, failed, status, &output)
+    }
+
+    /// Like [`SshConnection::push_file`] but transfers the file over the SFTP subsystem of the ssh server,
+    /// so no utilities need to be available on the instance.
+    /// On unix the permission bits of `source` are preserved.
+    pub async fn push_file_sftp(&self, source: &Path, dest: &Path) -> Result<()> {
+        tracing::info!(
+            "pushing file from {source:?} to {}:{dest:?} over sftp",
+            self.address
+        );
+        let file = File::open(source)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to read from {source:?}")))?;
+        let mode = local_permissions(&file.metadata().await?);
+        let dest = dest
+            .to_str()
+            .ok_or_else(|| anyhow!("{dest:?} is not valid UTF-8"))?;
+        let mut sftp = self.sftp().await?;
+        sftp.write_file(dest, file)
+            .await
+            .map_err(|e| e.context(format!("Failed to write to {}:{dest:?}", self.address)))?;
+        if let Some(mode) = mode {
+            sftp.set_permissions(dest, mode).await?;
+        }
+        Ok(())
+    }
+
+    /// Like [`SshConnection::pull_file`] but transfers the file over the SFTP subsystem of the ssh server,
+    /// so no utilities need to be available on the instance.
+    pub async fn pull_file_sftp(&self, source: &Path, dest: &Path) -> Result<()> {
+        tracing::info!(
+            "pulling file from {}:{source:?} to {dest:?} over sftp",
+            self.address
+        );
+        let out = File::create(dest)
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to create {dest:?}")))?;
+        let source = source
+            .to_str()
+            .ok_or_else(|| anyhow!("{source:?} is not valid UTF-8"))?;
+        self.sftp()
+            .await?
+            .read_file(source, out)
+            .await
+            .map_err(|e| e.context(format!("Failed to read from {}:{source:?}", self.address)))
+    }
+
+    /// Recursively copies the local directory `source` to `dest` on the instance over the SFTP subsystem of the ssh server,
+    /// so no utilities need to be available on the instance.
+    ///
+    /// `dest` becomes a copy of `source`, it is created if it does not exist.
+    /// If `dest` already exists the contents of `source` are merged into it:
+    /// files that exist in both are overwritten and files that only exist in `dest` are left untouched.
+    ///
+    /// Symlinks are recreated as symlinks with the same target, they are not followed.
+    /// On unix the permission bits of files and directories are preserved, elsewhere the server's defaults are used.
+    pub async fn push_dir(&self, source: &Path, dest: &Path) -> Result<()> {
+        tracing::info!(
+            "pushing directory from {source:?} to {}:{dest:?} over sftp",
+            self.address
+        );
+        let mut sftp = self.sftp().await?;
+        let mut pending = vec![(source.to_owned(), path_to_str(dest)?.to_owned())];
+        while let Some((local_dir, remote_dir)) = pending.pop() {
+            let metadata = tokio::fs::metadata(&local_dir)
+                .await
+                .map_err(|e| anyhow!(e).context(format!("Failed to read from {local_dir:?}")))?;
+            match sftp.lstat(&remote_dir).await? {
+                None => {
+                    sftp.mkdir(&remote_dir, local_permissions(&metadata))
+                        .await?
+                }
+                Some(attributes) if attributes.kind == FileKind::Directory => {}
+                Some(_) => {
+                    return Err(anyhow!(
+                        "{}:{remote_dir:?} already exists and is not a directory",
+                        self.address
+                    ))
+                }
+            }
+
+            let mut entries = tokio::fs::read_dir(&local_dir)
+                .await
+                .map_err(|e| anyhow!(e).context(format!("Failed to read from {local_dir:?}")))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let local = entry.path();
+                let remote = format!(
+                    "{remote_dir}/{}",
+                    path_to_str(Path::new(&entry.file_name()))?
+                );
+                let metadata = tokio::fs::symlink_metadata(&local).await?;
+                if metadata.is_symlink() {
+                    let target = tokio::fs::read_link(&local).await?;
+                    if sftp.lstat(&remote).await?.is_some() {
+                        sftp.remove(&remote).await?;
+                    }
+                    sftp.symlink(path_to_str(&target)?, &remote).await?;
+                } else if metadata.is_dir() {
+                    pending.push((local, remote));
+                } else {
+                    let file = File::open(&local).await.map_err(|e| {
+                        anyhow!(e).context(format!("Failed to read from {local:?}"))
+                    })?;
+                    sftp.write_file(&remote, file).await?;
+                    if let Some(permissions) = local_permissions(&metadata) {
+                        sftp.set_permissions(&remote, permissions).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursively copies the directory `source` on the instance to the local directory `dest` over the SFTP subsystem of the ssh server,
+    /// so no utilities need to be available on the instance.
+    ///
+    /// `dest` becomes a copy of `source`, it is created if it does not exist.
+    /// If `dest` already exists the contents of `source` are merged into it:
+    /// files that exist in both are overwritten and files that only exist in `dest` are left untouched.
+    ///
+    /// Symlinks are recreated as symlinks with the same target, they are not followed.
+    /// On unix the permission bits of files and directories are preserved.
+    /// Symlinks are skipped with a warning on platforms other than unix.
+    pub async fn pull_dir(&self, source: &Path, dest: &Path) -> Result<()> {
+        tracing::info!(
+            "pulling directory from {}:{source:?} to {dest:?} over sftp",
+            self.address
+        );
+        let mut sftp = self.sftp().await?;
+        let source = path_to_str(source)?;
+        let permissions = match sftp.lstat(source).await? {
+            Some(attributes) if attributes.kind == FileKind::Directory => attributes.permissions,
+            Some(_) => return Err(anyhow!("{}:{source:?} is not a directory", self.address)),
+            None => return Err(anyhow!("{}:{source:?} does not exist", self.address)),
+        };
+        let mut pending = vec![(source.to_owned(), dest.to_owned(), permissions)];
+        while let Some((remote_dir, local_dir, permissions)) = pending.pop() {
+            tokio::fs::create_dir_all(&local_dir)
+                .await
+                .map_err(|e| anyhow!(e).context(format!("Failed to create {local_dir:?}")))?;
+            set_local_permissions(&local_dir, permissions).await?;
+
+            for entry in sftp.read_dir(&remote_dir).await? {
+                let remote = format!("{remote_dir}/{}", entry.name);
+                let local = local_dir.join(&entry.name);
+                match entry.attributes.kind {
+                    FileKind::Symlink => {
+                        let target = sftp.read_link(&remote).await?;
+                        create_local_symlink(&target, &local).await?;
+                    }
+                    FileKind::Directory => {
+                        pending.push((remote, local, entry.attributes.permissions));
+                    }
+                    FileKind::File => {
+                        let file = File::create(&local).await.map_err(|e| {
+                            anyhow!(e).context(format!("Failed to create {local:?}"))
+                        })?;
+                        sftp.read_file(&remote, file).await?;
+                        set_local_permissions(&local, entry.attributes.permissions).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn sftp(&self) -> Result<SftpSession> {
+        let mut channel = self.session.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        loop {
+            match channel.wait().await {
+                Some(ChannelMsg::Success) => break,
+                Some(ChannelMsg::Failure) | None => bail!(
+                    "The ssh server at {} refused to start the sftp subsystem, sftp-server is probably not installed on the instance",
+                    self.address
+                ),
+                Some(_) => {}
+            }
+        }
+        SftpSession::new(channel.into_stream()).await
     }
 
     pub async fn pull_file(&self, source: &Path, dest: &Path) {
@@ -284,23 +921,212 @@ impl SshConnection {
     }
 }
 
+/// The output of a command as it is received by [`read_output`]
+#[derive(Default)]
+struct ReceivedOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_status: Option<u32>,
+}
+
+impl ReceivedOutput {
+    fn into_command_output(self) -> Result<CommandOutput> {
+        Ok(CommandOutput {
+            stdout: String::from_utf8(self.stdout)?,
+            stderr: String::from_utf8(self.stderr)?,
+            exit_status: self.exit_status,
+        })
+    }
+
+    /// Used for partial output, which may end part way through a UTF-8 character
+    fn into_command_output_lossy(self) -> CommandOutput {
+        CommandOutput {
+            stdout: String::from_utf8_lossy(&self.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&self.stderr).into_owned(),
+            exit_status: self.exit_status,
+        }
+    }
+}
+
+/// Collects the output of `channel` into `output` until the command run on it exits.
+/// If the future is dropped early, `output` still contains everything received up to that point.
+async fn read_output(channel: &mut Channel<Msg>, command: &str, output: &mut ReceivedOutput) {
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => output.stdout.write_all(&data).unwrap(),
+            ChannelMsg::ExtendedData { data, ext } => {
+                if ext == 1 {
+                    output.stderr.write_all(&data).unwrap()
+                } else {
+                    tracing::warn!(
+                        "received unknown extended data with extension type {ext} containing: {:?}",
+                        data.to_vec()
+                    )
+                }
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                output.exit_status = Some(exit_status);
+                // cant exit immediately, there might be more data still
+            }
+            ChannelMsg::ExitSignal {
+                signal_name,
+                core_dumped,
+                error_message,
+                ..
+            } => {
+                tracing::warn!("The command {command} was killed via signal {signal_name:?} core_dumped={core_dumped} {error_message:?}")
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends each complete line in `buffer` to `tx`, leaving any incomplete line in `buffer`.
+/// Returns false if the receiver has been dropped.
+fn send_lines(
+    buffer: &mut Vec<u8>,
+    tx: &tokio::sync::mpsc::UnboundedSender<ShellEvent>,
+    event: fn(String) -> ShellEvent,
+) -> bool {
+    while let Some(until) = buffer.iter().position(|c| *c == b'\n') {
+        let rest = buffer.split_off(until + 1);
+        buffer.pop(); // remove the '\n'
+        let line = String::from_utf8(std::mem::replace(buffer, rest)).unwrap();
+        if tx.send(event(line)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
 fn check_results<T: Display>(
     task: &str,
     failed: Option<String>,
     exit_status: Option<u32>,
     output: &T,
 ) {
+    if let Err(err) = command_result(task, failed, exit_status, output) {
+        panic!("{err}")
+    }
+}
+
+fn command_result<T: Display>(
+    task: &str,
+    failed: Option<String>,
+    exit_status: Option<u32>,
+    output: &T,
+) -> Result<()> {
     if let Some(failed) = failed {
-        panic!("{task:?} was {failed}\n{output}")
+        bail!("{task:?} was {failed}\n{output}")
     }
 
     match exit_status {
         Some(status) => {
             if status != 0 {
-                panic!("{task} failed with exit code {status}\n{output}")
+                bail!("{task} failed with exit code {status}\n{output}")
             }
         }
-        None => panic!("{task} did not exit cleanly\n{output}"),
+        None => bail!("{task} did not exit cleanly\n{output}"),
+    }
+    Ok(())
+}
+
+/// An event emitted by [`SshConnection::shell_stream`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellEvent {
+    /// A line written to stdout, without the trailing newline
+    Stdout(String),
+    /// A line written to stderr, without the trailing newline
+    Stderr(String),
+    /// The command has finished, contains the exit status or `None` if the command was killed by a signal
+    Exited(Option<u32>),
+}
+
+/// Quotes `value` so that it is interpreted literally by the shell
+pub(crate) fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Builds a script that exports each `(name, value)` pair in `env` and then runs `command`
+fn env_script(env: &[(&str, &str)], command: &str) -> String {
+    let mut script = String::new();
+    for (name, value) in env {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        assert!(valid, "{name:?} is not a valid environment variable name");
+        script.push_str(&format!("export {name}={}\n", shell_escape(value)));
+    }
+    script.push_str(command);
+    script
+}
+
+fn path_to_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("{path:?} is not valid UTF-8"))
+}
+
+#[cfg(unix)]
+pub(crate) fn local_permissions(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn local_permissions(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+async fn set_local_permissions(path: &Path, permissions: Option<u32>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(permissions) = permissions {
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(permissions))
+            .await
+            .map_err(|e| anyhow!(e).context(format!("Failed to set permissions of {path:?}")))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn set_local_permissions(_path: &Path, _permissions: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn create_local_symlink(target: &str, link: &Path) -> Result<()> {
+    if tokio::fs::symlink_metadata(link).await.is_ok() {
+        tokio::fs::remove_file(link).await?;
+    }
+    tokio::fs::symlink(target, link)
+        .await
+        .map_err(|e| anyhow!(e).context(format!("Failed to create symlink {link:?}")))
+}
+
+#[cfg(not(unix))]
+async fn create_local_symlink(target: &str, link: &Path) -> Result<()> {
+    tracing::warn!("skipping symlink {link:?} -> {target:?}, symlinks are only supported on unix");
+    Ok(())
+}
+
+/// An ssh tunnel created by [`SshConnection::forward_local_port`], the tunnel is closed when this is dropped.
+pub struct PortForward {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl PortForward {
+    /// The local address that is forwarded to the instance
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        self.task.abort();
     }
 }
 
@@ -308,6 +1134,8 @@ fn check_results<T: Display>(
 pub struct CommandOutput {
     pub stdout: String,
     pub stderr: String,
+    /// The exit status of the command, `None` if it was killed by a signal or otherwise did not exit cleanly.
+    pub exit_status: Option<u32>,
 }
 
 impl Display for CommandOutput {
@@ -346,3 +1174,189 @@ impl Handler for Client {
         Ok((self, result))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_key::rand_core::OsRng;
+    use ssh_key::PrivateKey;
+
+    /// An ssh server that accepts any client key and runs commands on the local machine via `sh`
+    struct TestServer;
+
+    #[async_trait]
+    impl russh::server::Handler for TestServer {
+        type Error = anyhow::Error;
+
+        async fn auth_publickey(
+            self,
+            _user: &str,
+            _public_key: &PublicKey,
+        ) -> Result<(Self, russh::server::Auth)> {
+            Ok((self, russh::server::Auth::Accept))
+        }
+
+        async fn channel_open_session(
+            self,
+            channel: Channel<russh::server::Msg>,
+            session: russh::server::Session,
+        ) -> Result<(Self, bool, russh::server::Session)> {
+            tokio::spawn(run_session(channel, session.handle()));
+            Ok((self, true, session))
+        }
+    }
+
+    /// Runs the command exec'd on `channel` with `sh`, forwarding the channel's data to its stdin and its output back to the channel
+    async fn run_session(mut channel: Channel<russh::server::Msg>, handle: russh::server::Handle) {
+        let id = channel.id();
+        let mut stdin = None;
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Exec { command, .. } => {
+                    let mut child = tokio::process::Command::new("sh")
+                        .arg("-c")
+                        .arg(String::from_utf8(command).unwrap())
+                        .stdin(std::process::Stdio::piped())
+                        .stdout(std::process::Stdio::piped())
+                        .stderr(std::process::Stdio::piped())
+                        .spawn()
+                        .unwrap();
+                    stdin = child.stdin.take();
+                    let handle = handle.clone();
+                    tokio::spawn(async move {
+                        let output = child.wait_with_output().await.unwrap();
+                        handle
+                            .data(id, russh::CryptoVec::from(output.stdout))
+                            .await
+                            .ok();
+                        handle
+                            .extended_data(id, 1, russh::CryptoVec::from(output.stderr))
+                            .await
+                            .ok();
+                        let status = output.status.code().unwrap_or(255) as u32;
+                        handle.exit_status_request(id, status).await.ok();
+                        handle.eof(id).await.ok();
+                        handle.close(id).await.ok();
+                    });
+                }
+                ChannelMsg::Data { data } => {
+                    if let Some(stdin) = &mut stdin {
+                        tokio::io::AsyncWriteExt::write_all(stdin, &data)
+                            .await
+                            .unwrap();
+                    }
+                }
+                ChannelMsg::Eof => stdin = None,
+                _ => {}
+            }
+        }
+    }
+
+    fn random_key() -> PrivateKey {
+        PrivateKey::random(OsRng {}, ssh_key::Algorithm::Ed25519).unwrap()
+    }
+
+    /// Credentials that trust `host_key`, with a fresh client key
+    fn credentials_for(host_key: &PrivateKey) -> SshCredentials {
+        SshCredentials {
+            host_public_key_bytes: host_key.public_key().to_bytes().unwrap(),
+            client_private_key: random_key()
+                .to_openssh(ssh_key::LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            user: "ubuntu".to_owned(),
+            jump_host: None,
+        }
+    }
+
+    /// Connects to an in memory ssh server that uses `server_host_key`, as if it were reachable at `address`
+    async fn connect(
+        address: IpAddr,
+        server_host_key: &PrivateKey,
+        credentials: &SshCredentials,
+    ) -> std::result::Result<SshConnection, SshConnectError> {
+        let host_key_pair = russh_keys::decode_secret_key(
+            &server_host_key.to_openssh(ssh_key::LineEnding::LF).unwrap(),
+            None,
+        )
+        .unwrap();
+        let config = Arc::new(russh::server::Config {
+            keys: vec![host_key_pair],
+            ..Default::default()
+        });
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            if let Ok(session) = russh::server::run_stream(config, server_stream, TestServer).await
+            {
+                session.await.ok();
+            }
+        });
+        SshConnection::new(Box::new(client_stream), address, credentials, None).await
+    }
+
+    #[tokio::test]
+    async fn host_key_verified_after_ip_change() {
+        let host_key = random_key();
+        let credentials = credentials_for(&host_key);
+
+        // The instance is stopped and started, receiving a new public IP but keeping its host key.
+        let before = connect("203.0.113.1".parse().unwrap(), &host_key, &credentials).await;
+        assert!(before.is_ok());
+        let after = connect("203.0.113.2".parse().unwrap(), &host_key, &credentials).await;
+        assert!(after.is_ok());
+
+        // A different host at the new IP is still rejected.
+        let imposter = connect("203.0.113.2".parse().unwrap(), &random_key(), &credentials).await;
+        assert!(matches!(imposter, Err(SshConnectError::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn run_script_without_executable_bit() {
+        let host_key = random_key();
+        let credentials = credentials_for(&host_key);
+        let Ok(ssh) = connect("203.0.113.1".parse().unwrap(), &host_key, &credentials).await
+        else {
+            panic!("Failed to connect to the test server");
+        };
+
+        let script = std::env::temp_dir().join(format!("aws-throwaway-test-{}", Uuid::new_v4()));
+        std::fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        let output = ssh.run_script(&script).await;
+        std::fs::remove_file(&script).unwrap();
+
+        assert_eq!(output.stdout, "hello\n");
+        assert_eq!(output.exit_status, Some(0));
+    }
+
+    #[test]
+    fn env_script_escapes_values() {
+        assert_eq!(
+            env_script(&[("FOO", "a b"), ("_BAR2", "it's")], "echo $FOO"),
+            "export FOO='a b'\nexport _BAR2='it'\\''s'\necho $FOO"
+        );
+        assert_eq!(env_script(&[], "true"), "true");
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a valid environment variable name")]
+    fn env_script_rejects_injected_name() {
+        env_script(&[("FOO=x; rm -rf ~ #", "value")], "true");
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a valid environment variable name")]
+    fn env_script_rejects_leading_digit() {
+        env_script(&[("1FOO", "value")], "true");
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a valid environment variable name")]
+    fn env_script_rejects_empty_name() {
+        env_script(&[("", "value")], "true");
+    }
+}